@@ -0,0 +1,535 @@
+//! Credentials for the GitHub API.
+//!
+//! Requests authenticate with a personal access token (GHP_TOKEN), a token saved by `ygg login`, the GitHub CLI's
+//! token, or as a GitHub App installation. App installation tokens expire after an hour, so they are minted on first use from a
+//! short-lived JWT signed with the app's private key and minted again shortly before they expire; long runs never
+//! see a 401 midway.
+
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::{Client, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{api_url, unix_now, Config, GitHubClient, NetworkOptions, Result, YggError};
+
+/// Installation tokens are valid for an hour; mint a new one this long before that.
+const INSTALLATION_TOKEN_LIFETIME: Duration = Duration::from_secs(55 * 60);
+
+/// App JWTs may be valid for at most ten minutes; issue them slightly backdated to allow for clock drift.
+const JWT_LIFETIME_SECS: u64 = 9 * 60;
+const JWT_BACKDATE_SECS: u64 = 60;
+
+/// GitHub App credentials: the app's ID, its private key, and the installation to act as.
+pub(crate) struct AppCredentials {
+    app_id: String,
+    private_key: EncodingKey,
+    /// The installation to mint tokens for. If unset, the app must have exactly one installation.
+    installation_id: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iat: u64,
+    exp: u64,
+    iss: &'a str,
+}
+
+#[derive(Deserialize)]
+struct InstallationToken {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct Installation {
+    id: u64,
+    account: Option<Account>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    login: String,
+}
+
+/// Where request credentials come from.
+pub(crate) enum TokenSource {
+    /// Tokens used as is, e.g. personal access tokens, rotated across to pool their rate limits.
+    Tokens(Vec<String>),
+    /// A GitHub App, with its current installation token and when to replace it.
+    App { credentials: AppCredentials, current: tokio::sync::Mutex<Option<(String, u64)>> },
+    /// No credentials (--offline).
+    None,
+}
+
+impl AppCredentials {
+    /// Reads app credentials from GH_APP_ID, GH_APP_PRIVATE_KEY_PATH and GH_APP_INSTALLATION_ID, falling back to
+    /// the "app_id", "app_private_key_path" and "app_installation_id" keys in .ygg.toml. Returns None if no app ID
+    /// is configured.
+    pub(crate) fn from_env(config: &Config) -> Result<Option<Self>> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        let Some(app_id) = var("GH_APP_ID").or_else(|| config.app_id.clone()) else {
+            return Ok(None);
+        };
+        let key_path = var("GH_APP_PRIVATE_KEY_PATH")
+            .or_else(|| config.app_private_key_path.clone())
+            .ok_or_else(|| YggError::Config("GitHub App authentication needs GH_APP_PRIVATE_KEY_PATH".to_string()))?;
+        let pem = fs::read(&key_path)
+            .map_err(|e| YggError::Config(format!("can't read the GitHub App private key in {key_path}: {e}")))?;
+        let private_key = EncodingKey::from_rsa_pem(&pem)
+            .map_err(|e| YggError::Config(format!("invalid GitHub App private key in {key_path}: {e}")))?;
+        let installation_id = match var("GH_APP_INSTALLATION_ID") {
+            Some(id) => Some(id.parse().map_err(|_| YggError::Config(format!("invalid GH_APP_INSTALLATION_ID: {id}")))?),
+            None => config.app_installation_id,
+        };
+        Ok(Some(Self { app_id, private_key, installation_id }))
+    }
+
+    /// Returns a JWT authenticating as the app itself.
+    fn jwt(&self) -> Result<String> {
+        let now = unix_now();
+        let claims = Claims { iat: now - JWT_BACKDATE_SECS, exp: now + JWT_LIFETIME_SECS, iss: &self.app_id };
+        jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.private_key)
+            .map_err(|e| YggError::Config(format!("can't sign the GitHub App JWT: {e}")))
+    }
+
+    /// Mints an installation token. Sent directly rather than through `GitHubClient::send`, which authenticates
+    /// with the token this produces.
+    async fn mint(&self, client: &Client) -> Result<String> {
+        let jwt = self.jwt()?;
+        let request = |method: Method, url: String| {
+            client
+                .request(method, url)
+                .header("Authorization", format!("Bearer {jwt}"))
+                .header("Accept", "application/vnd.github+json")
+                .header("X-GitHub-Api-Version", "2022-11-28")
+        };
+
+        let installation_id = match self.installation_id {
+            Some(id) => id,
+            None => {
+                let resp = request(Method::GET, format!("{}/app/installations", api_url())).send().await?;
+                if !resp.status().is_success() {
+                    return Err(YggError::ApiError(format!("listing GitHub App installations failed: {}", resp.status())));
+                }
+                match resp.json::<Vec<Installation>>().await?.as_slice() {
+                    [installation] => installation.id,
+                    installations => {
+                        let accounts: Vec<&str> = installations
+                            .iter()
+                            .filter_map(|installation| installation.account.as_ref().map(|account| account.login.as_str()))
+                            .collect();
+                        return Err(YggError::Config(format!(
+                            "the GitHub App has {} installations ({}); set GH_APP_INSTALLATION_ID",
+                            installations.len(),
+                            accounts.join(", ")
+                        )));
+                    }
+                }
+            }
+        };
+
+        let url = format!("{}/app/installations/{installation_id}/access_tokens", api_url());
+        let resp = request(Method::POST, url).send().await?;
+        match resp.status() {
+            status if status.is_success() => Ok(resp.json::<InstallationToken>().await?.token),
+            StatusCode::UNAUTHORIZED => {
+                Err(YggError::Config("GitHub App authentication was rejected; check the app ID and private key".to_string()))
+            }
+            status => Err(YggError::ApiError(format!("minting a GitHub App installation token failed: {status}"))),
+        }
+    }
+}
+
+/// Every credential of a run: the default source, plus tokens for specific organizations or users from the
+/// "org_tokens" table in .ygg.toml. Requests are numbered into slots, each with its own rate limit: the default
+/// source's tokens first, then the owner tokens.
+pub(crate) struct Credentials {
+    default: TokenSource,
+    owner_tokens: Vec<(String, String)>,
+}
+
+impl Credentials {
+    /// `owner_tokens` maps lowercased owners to their token.
+    pub(crate) fn new(default: TokenSource, owner_tokens: BTreeMap<String, String>) -> Self {
+        Self { default, owner_tokens: owner_tokens.into_iter().collect() }
+    }
+
+    /// Returns the number of slots.
+    pub(crate) fn len(&self) -> usize {
+        self.default.len() + self.owner_tokens.len()
+    }
+
+    /// Returns the number of default slots, which requests for owners without a token of their own rotate across.
+    pub(crate) fn pool_len(&self) -> usize {
+        self.default.len()
+    }
+
+    /// Returns the slot of `owner`'s own token, if it has one.
+    pub(crate) fn owner_slot(&self, owner: &str) -> Option<usize> {
+        let index = self.owner_tokens.iter().position(|(name, _)| name.eq_ignore_ascii_case(owner))?;
+        Some(self.default.len() + index)
+    }
+
+    pub(crate) fn is_app(&self, slot: usize) -> bool {
+        slot < self.default.len() && matches!(self.default, TokenSource::App { .. })
+    }
+
+    /// Names the token in `slot` for messages.
+    pub(crate) fn describe(&self, slot: usize) -> String {
+        match slot.checked_sub(self.default.len()) {
+            Some(index) => format!("the token for {}", self.owner_tokens[index].0),
+            None if self.default.len() > 1 => format!("token {} of {}", slot + 1, self.default.len()),
+            None => "the token".to_string(),
+        }
+    }
+
+    pub(crate) async fn authorization(&self, slot: usize, client: &Client) -> Result<Option<String>> {
+        match slot.checked_sub(self.default.len()) {
+            Some(index) => Ok(Some(format!("token {}", self.owner_tokens[index].1))),
+            None => self.default.authorization(slot, client).await,
+        }
+    }
+}
+
+/// Reads the "org_tokens" table of .ygg.toml, keyed by lowercased owner. A value starting with "$" names an
+/// environment variable holding the token, so the config file itself can be committed.
+pub(crate) fn owner_tokens(config: &Config) -> Result<BTreeMap<String, String>> {
+    config
+        .org_tokens
+        .iter()
+        .map(|(owner, value)| {
+            let token = match value.strip_prefix('$') {
+                Some(var) => env::var(var)
+                    .ok()
+                    .filter(|token| !token.is_empty())
+                    .ok_or_else(|| YggError::Config(format!("org_tokens.{owner} names {var}, which is not set")))?,
+                None => value.clone(),
+            };
+            Ok((owner.to_lowercase(), token.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Returns the owner a REST request is for: the owner in /repos/{owner}/..., /orgs/{org}/... and
+/// /users/{user}/... paths, or in the org:, user: or repo: qualifier of a search. GraphQL requests can span owners,
+/// so they have none.
+pub(crate) fn request_owner(url: &reqwest::Url) -> Option<String> {
+    let base = reqwest::Url::parse(api_url()).ok()?;
+    let path = url.path().strip_prefix(base.path().trim_end_matches('/'))?;
+    let mut segments = path.trim_start_matches('/').split('/');
+    match segments.next()? {
+        "repos" | "orgs" | "users" => segments.next().filter(|owner| !owner.is_empty()).map(str::to_lowercase),
+        "search" => {
+            let (_, query) = url.query_pairs().find(|(key, _)| key == "q")?;
+            let owner = query.split_whitespace().find_map(|term| {
+                term.strip_prefix("org:")
+                    .or_else(|| term.strip_prefix("user:"))
+                    .or_else(|| term.strip_prefix("repo:").and_then(|repo| repo.split('/').next()))
+            });
+            owner.map(str::to_lowercase)
+        }
+        _ => None,
+    }
+}
+
+impl TokenSource {
+    /// Returns how many tokens requests rotate across, each with its own rate limit.
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::Tokens(tokens) => tokens.len(),
+            Self::App { .. } | Self::None => 1,
+        }
+    }
+
+    /// Returns the Authorization header value for a request sent with the token at `slot`, minting a new
+    /// installation token if the current one is missing or about to expire.
+    pub(crate) async fn authorization(&self, slot: usize, client: &Client) -> Result<Option<String>> {
+        match self {
+            Self::Tokens(tokens) => Ok(Some(format!("token {}", tokens[slot]))),
+            Self::App { credentials, current } => {
+                // Held while minting, so concurrent requests wait for one new token instead of each minting their own
+                let mut current = current.lock().await;
+                if let Some((token, _)) = current.as_ref().filter(|(_, expires_at)| *expires_at > unix_now()) {
+                    return Ok(Some(format!("token {token}")));
+                }
+                let token = credentials.mint(client).await?;
+                *current = Some((token.clone(), unix_now() + INSTALLATION_TOKEN_LIFETIME.as_secs()));
+                Ok(Some(format!("token {token}")))
+            }
+            Self::None => Ok(None),
+        }
+    }
+}
+
+/// Scopes `ygg login` requests: reading repository contents, and organization membership for SSO and discovery.
+pub(crate) const LOGIN_SCOPES: &str = "repo read:org";
+
+const CREDENTIALS_FILE: &str = "credentials.toml";
+
+/// Service name ygg's entries are stored under in the OS keyring, with the host as the account.
+const KEYRING_SERVICE: &str = "ygg";
+
+/// Tokens saved by `ygg login`, by host, so GitHub.com and Enterprise Server logins can live side by side.
+#[derive(Deserialize, Serialize, Default)]
+struct StoredCredentials {
+    #[serde(default)]
+    hosts: BTreeMap<String, String>,
+    /// Hosts whose token is in the OS keyring (`ygg login --keyring`) rather than this file.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    keyring: BTreeSet<String>,
+}
+
+impl StoredCredentials {
+    fn load() -> Result<Self> {
+        match fs::read_to_string(credentials_path()) {
+            Ok(data) => Ok(toml::from_str(&data)?),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+}
+
+/// Runs `f` against this run's host's keyring entry. The Secret Service backend drives D-Bus with a runtime of its
+/// own, which can't start on a thread that is already running tokio, so the call is made on a separate thread.
+fn with_keyring<T: Send>(f: impl FnOnce(&keyring::Entry) -> keyring::Result<T> + Send) -> Result<T> {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| f(&keyring::Entry::new(KEYRING_SERVICE, web_host())?))
+            .join()
+            .expect("keyring access panicked")
+    })
+    .map_err(YggError::from)
+}
+
+/// Returns `$XDG_CONFIG_HOME/ygg/credentials.toml`, falling back to `~/.config/ygg/credentials.toml`.
+fn credentials_path() -> PathBuf {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from(".config"));
+    base.join("ygg").join(CREDENTIALS_FILE)
+}
+
+/// Returns the web host of the API this run talks to, e.g. "github.com" or "github.example.com".
+pub(crate) fn web_host() -> &'static str {
+    let host = api_url().trim_start_matches("https://").trim_start_matches("http://");
+    let host = host.split('/').next().unwrap_or(host);
+    host.strip_prefix("api.").unwrap_or(host)
+}
+
+/// Returns the tokens to authenticate as a user with: those in GHP_TOKEN (comma-separated), the "tokens" key in
+/// .ygg.toml, and the file named by GHP_TOKENS_FILE or "tokens_file" (one per line, "#" starts a comment).
+/// Without any, falls back to the token `ygg login` saved, then the GitHub CLI's.
+pub(crate) fn personal_tokens(config: &Config) -> Result<Vec<String>> {
+    let mut tokens: Vec<String> = env::var("GHP_TOKEN").unwrap_or_default().split(',').map(str::to_string).collect();
+    tokens.extend(config.tokens.iter().cloned());
+    let tokens_file = env::var("GHP_TOKENS_FILE").ok().filter(|path| !path.is_empty()).or_else(|| config.tokens_file.clone());
+    if let Some(path) = tokens_file {
+        let data = fs::read_to_string(&path).map_err(|e| YggError::Config(format!("can't read tokens file {path}: {e}")))?;
+        tokens.extend(data.lines().map(|line| line.split('#').next().unwrap_or_default().to_string()));
+    }
+
+    let mut seen = BTreeSet::new();
+    let tokens: Vec<String> =
+        tokens.into_iter().map(|token| token.trim().to_string()).filter(|token| !token.is_empty() && seen.insert(token.clone())).collect();
+    if !tokens.is_empty() {
+        return Ok(tokens);
+    }
+    Ok(stored_token().or_else(gh_cli_token).into_iter().collect())
+}
+
+/// Returns the token `ygg login` saved for this run's host, if any.
+fn stored_token() -> Option<String> {
+    let mut credentials = StoredCredentials::load().ok()?;
+    if !credentials.keyring.contains(web_host()) {
+        return credentials.hosts.remove(web_host());
+    }
+    match with_keyring(|entry| entry.get_password()) {
+        Ok(token) => Some(token),
+        Err(e) => {
+            eprintln!("Warning: can't read the saved token for {}: {e}", web_host());
+            None
+        }
+    }
+}
+
+/// Returns the token the GitHub CLI is signed in with for this run's host: `gh auth token`, which also covers
+/// tokens gh keeps in the OS keyring, then the `oauth_token` in gh's hosts.yml if gh isn't on the PATH.
+fn gh_cli_token() -> Option<String> {
+    let output = std::process::Command::new("gh").args(["auth", "token", "--hostname", web_host()]).output();
+    if let Ok(output) = output {
+        let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        return (output.status.success() && !token.is_empty()).then_some(token);
+    }
+
+    let dir = env::var_os("GH_CONFIG_DIR").filter(|dir| !dir.is_empty()).map(PathBuf::from).or_else(|| {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| PathBuf::from(dir).join("gh"))
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("gh")))
+    })?;
+    let hosts: BTreeMap<String, GhHost> = serde_yaml::from_str(&fs::read_to_string(dir.join("hosts.yml")).ok()?).ok()?;
+    hosts.get(web_host())?.oauth_token.clone().filter(|token| !token.is_empty())
+}
+
+/// A host entry in the GitHub CLI's hosts.yml.
+#[derive(Deserialize)]
+struct GhHost {
+    oauth_token: Option<String>,
+}
+
+/// Saves `token` for this run's host in the OS keyring, or in the credentials file, readable only by the current
+/// user. Returns where it was saved.
+fn store_token(token: &str, use_keyring: bool) -> Result<String> {
+    let path = credentials_path();
+    let mut credentials = StoredCredentials::load()?;
+    let host = web_host().to_string();
+    let location = if use_keyring {
+        with_keyring(|entry| entry.set_password(token))?;
+        credentials.hosts.remove(&host);
+        credentials.keyring.insert(host);
+        "the OS keyring".to_string()
+    } else {
+        credentials.keyring.remove(&host);
+        credentials.hosts.insert(host, token.to_string());
+        path.display().to_string()
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path)?;
+    #[cfg(unix)]
+    fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(toml::to_string(&credentials)?.as_bytes())?;
+    Ok(location)
+}
+
+#[derive(Deserialize)]
+struct DeviceCode {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    expires_in: u64,
+    interval: u64,
+}
+
+#[derive(Deserialize)]
+struct DeviceToken {
+    access_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+    interval: Option<u64>,
+}
+
+/// Signs in through the OAuth device flow: shows a code to enter in the browser, waits for the user to approve
+/// it, and saves the resulting token for later runs, in the OS keyring if `use_keyring` is set. `client_id` is the
+/// OAuth app the token is issued to, which must have device flow enabled.
+pub(crate) async fn login(client_id: &str, scopes: &str, use_keyring: bool, network: &NetworkOptions) -> Result<()> {
+    let client = network.github_client_builder()?.user_agent("ygg/0.1").https_only(true).build()?;
+    let web_url = format!("https://{}", web_host());
+
+    let resp = client
+        .post(format!("{web_url}/login/device/code"))
+        .header("Accept", "application/json")
+        .form(&[("client_id", client_id), ("scope", scopes)])
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        return Err(YggError::ApiError(format!("requesting a device code failed: {}", resp.status())));
+    }
+    let code: DeviceCode = resp.json().await?;
+
+    eprintln!("Open {} and enter the code {}", code.verification_uri, code.user_code);
+    let deadline = unix_now() + code.expires_in;
+    let mut interval = code.interval.max(1);
+    loop {
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        if unix_now() >= deadline {
+            return Err(YggError::Aborted("the device code expired before it was entered; run ygg login again".to_string()));
+        }
+
+        let resp = client
+            .post(format!("{web_url}/login/oauth/access_token"))
+            .header("Accept", "application/json")
+            .form(&[
+                ("client_id", client_id),
+                ("device_code", code.device_code.as_str()),
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+            ])
+            .send()
+            .await?;
+        let token: DeviceToken = resp.json().await?;
+        match (token.access_token, token.error.as_deref()) {
+            (Some(access_token), _) => {
+                let location = store_token(&access_token, use_keyring)?;
+                eprintln!("Logged in to {}; the token is saved in {location}", web_host());
+                return Ok(());
+            }
+            (None, Some("authorization_pending")) => {}
+            (None, Some("slow_down")) => interval = token.interval.unwrap_or(interval + 5),
+            (None, Some("access_denied")) => return Err(YggError::Aborted("the login was denied in the browser".to_string())),
+            (None, error) => {
+                return Err(YggError::ApiError(format!(
+                    "login failed: {}",
+                    token.error_description.as_deref().or(error).unwrap_or("no token in the response")
+                )))
+            }
+        }
+    }
+}
+
+/// Checks every credential before a run fans out, so a revoked or under-scoped token fails once with a clear
+/// message instead of as a wall of identical 401s. Classic tokens list their scopes in X-OAuth-Scopes, which must
+/// include "repo" to read private repositories; fine-grained tokens don't, and are only checked for validity.
+pub(crate) async fn validate(gh_client: &GitHubClient) -> Result<()> {
+    if gh_client.offline {
+        return Ok(());
+    }
+    for slot in 0..gh_client.auth.len() {
+        let which = gh_client.auth.describe(slot);
+        // Installation tokens can't read /user
+        let url = if gh_client.auth.is_app(slot) {
+            format!("{}/installation/repositories?per_page=1", api_url())
+        } else {
+            format!("{}/user", api_url())
+        };
+        let resp = gh_client.send_as(gh_client.api_request(Method::GET, &url), Some(slot)).await?;
+        match resp.status() {
+            StatusCode::UNAUTHORIZED => {
+                return Err(YggError::Unauthorized(format!(
+                    "GitHub rejected {which} for {}; it may be expired or revoked",
+                    web_host()
+                )))
+            }
+            status if !status.is_success() => return Err(YggError::UnexpectedStatus(status)),
+            _ => {}
+        }
+
+        let Some(scopes) = resp.headers().get("X-OAuth-Scopes").and_then(|v| v.to_str().ok()) else {
+            continue;
+        };
+        let scopes: Vec<&str> = scopes.split(',').map(str::trim).collect();
+        if scopes.contains(&"repo") {
+            continue;
+        }
+        if scopes.contains(&"public_repo") {
+            eprintln!("Warning: {which} only has the public_repo scope; private repositories will be reported as not found");
+            continue;
+        }
+        return Err(YggError::Unauthorized(format!(
+            "{which} can't read repository contents (scopes: {}); it needs the repo scope",
+            if scopes.iter().all(|scope| scope.is_empty()) { "none".to_string() } else { scopes.join(", ") }
+        )));
+    }
+    Ok(())
+}
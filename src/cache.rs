@@ -0,0 +1,417 @@
+//! Local cache of fetched files, revalidated with ETags.
+//!
+//! Bodies are stored content-addressed by SHA-256, so byte-identical files shared by forked or templated
+//! repositories are stored once, and zstd-compressed. Each cached URI has a small entry pointing at its body.
+//! Entries and bodies live either in a directory of files (`files`) or in a single SQLite database (`sqlite`),
+//! optionally backed by a shared remote cache (`remote`).
+
+mod files;
+mod remote;
+mod sqlite;
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub(crate) use remote::RemoteCache;
+
+use crate::{sso_authorization_url, unix_now, ApiErrorBody, FetchedFile, GitHubClient, Result, YggError};
+
+/// Returns the hex-encoded SHA-256 of `body`.
+pub(crate) fn content_hash(body: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(body))
+}
+
+const RAW_MEDIA_TYPE: &str = "application/vnd.github.v3.raw";
+
+/// zstd compression level for cached bodies. Lockfiles compress around 10x even at low levels.
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Magic number at the start of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+fn compress(body: &[u8]) -> Option<Vec<u8>> {
+    zstd::encode_all(body, COMPRESSION_LEVEL).ok()
+}
+
+/// Decompresses a stored body. Bodies written before compression was introduced are returned as-is.
+fn decompress(data: Vec<u8>) -> Result<Vec<u8>> {
+    if data.starts_with(&ZSTD_MAGIC) {
+        Ok(zstd::decode_all(data.as_slice())?)
+    } else {
+        Ok(data)
+    }
+}
+
+/// Cache metadata for one URI.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct Entry {
+    uri: String,
+    etag: Option<String>,
+    /// Content hash naming the stored body.
+    hash: String,
+    /// Unix timestamp of the last time the body was fetched or revalidated.
+    fetched_at: u64,
+    /// Unix timestamp of the last time the entry was used, for LRU eviction.
+    #[serde(default)]
+    accessed_at: u64,
+}
+
+/// Runs whose hit and miss counters are kept.
+const RUN_HISTORY_LEN: usize = 10;
+
+/// Cache hit and miss counters for one run.
+#[derive(Deserialize, Serialize, Debug)]
+pub(crate) struct RunRecord {
+    pub(crate) finished_at: u64,
+    pub(crate) hits: usize,
+    pub(crate) misses: usize,
+}
+
+/// Upper bounds, in seconds, of the age buckets reported by `ygg cache stats`.
+pub(crate) const AGE_BUCKETS: [(&str, u64); 4] =
+    [("< 1 hour", 3600), ("< 1 day", 86_400), ("< 1 week", 7 * 86_400), ("< 30 days", 30 * 86_400)];
+
+/// Summary of the cache contents.
+pub(crate) struct CacheStats {
+    pub(crate) entries: usize,
+    pub(crate) notfound_markers: usize,
+    pub(crate) objects: usize,
+    /// Size of the compressed bodies on disk.
+    pub(crate) object_bytes: u64,
+    /// Entry counts per `AGE_BUCKETS` bucket by time since last fetch, plus a final bucket for older entries.
+    pub(crate) ages: [usize; AGE_BUCKETS.len() + 1],
+    pub(crate) runs: Vec<RunRecord>,
+}
+
+/// Buckets entries by time since their last fetch.
+fn age_histogram(fetched_at: impl Iterator<Item = u64>) -> [usize; AGE_BUCKETS.len() + 1] {
+    let now = unix_now();
+    let mut ages = [0; AGE_BUCKETS.len() + 1];
+    for fetched_at in fetched_at {
+        let age = now.saturating_sub(fetched_at);
+        let bucket = AGE_BUCKETS.iter().position(|&(_, limit)| age < limit).unwrap_or(AGE_BUCKETS.len());
+        ages[bucket] += 1;
+    }
+    ages
+}
+
+/// Selects what `ygg cache gc` prunes.
+pub(crate) struct GcFilter<'a> {
+    /// Prune entries last fetched at least this long ago.
+    pub(crate) older_than: Option<Duration>,
+    /// Prune entries for repositories under any of these `org` or `org/repo` prefixes.
+    pub(crate) prefixes: &'a [String],
+    /// Prune entries whose body is missing and not-found markers whose URI is unknown.
+    pub(crate) orphans: bool,
+}
+
+impl GcFilter<'_> {
+    /// Returns true if the age and prefix criteria select an entry. With neither given, nothing is selected.
+    fn selects(&self, uri: Option<&str>, age: u64) -> bool {
+        (self.older_than.is_some() || !self.prefixes.is_empty())
+            && self.older_than.map_or(true, |older_than| age >= older_than.as_secs())
+            && (self.prefixes.is_empty()
+                || uri.is_some_and(|uri| self.prefixes.iter().any(|prefix| uri_has_prefix(uri, prefix))))
+    }
+}
+
+/// Counts of what a `gc` pass removed.
+#[derive(Default)]
+pub(crate) struct GcReport {
+    pub(crate) entries: usize,
+    pub(crate) notfound_markers: usize,
+    pub(crate) objects: usize,
+    pub(crate) bytes: u64,
+}
+
+/// Returns true if `uri` belongs to a repository under `prefix`, comparing whole path components.
+fn uri_has_prefix(uri: &str, prefix: &str) -> bool {
+    let path = uri.strip_prefix(&format!("{}/repos/", crate::api_url())).unwrap_or(uri);
+    path.starts_with(&format!("{}/", prefix.trim_end_matches('/')))
+}
+
+/// Outcome of an eviction pass.
+#[derive(Default)]
+pub(crate) struct Eviction {
+    pub(crate) entries: usize,
+    pub(crate) bytes: u64,
+}
+
+/// Where cache entries and bodies are kept.
+#[derive(clap::ValueEnum, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CacheBackend {
+    /// A directory of JSON entries and body files
+    #[default]
+    Files,
+    /// A single SQLite database
+    Sqlite,
+}
+
+/// Storage for cache entries, bodies and not-found markers. Writes are best-effort: a cache that can't be
+/// written only costs extra requests on the next run.
+trait Store: Send + Sync {
+    /// Returns when the URI was last found not to exist, if it has a not-found marker.
+    fn notfound_since(&self, uri: &str) -> Option<u64>;
+    /// Records that the URI does not exist, dropping any entry for it.
+    fn mark_notfound(&self, uri: &str);
+    /// Returns the entry for the URI if both it and its body are present.
+    fn read_entry(&self, uri: &str) -> Option<Entry>;
+    /// Reads and decompresses the body stored under `hash`.
+    fn read_body(&self, hash: &str) -> Result<Vec<u8>>;
+    /// Writes the entry, storing the body unless one with the same hash is already stored, and clears any
+    /// not-found marker for the URI.
+    fn write_entry(&self, entry: &Entry, body: &[u8]);
+    /// Drops the entry and not-found marker for the URI.
+    fn invalidate(&self, uri: &str);
+    /// Evicts least-recently-used entries until the compressed bodies fit within `max_bytes`. Bodies shared by
+    /// several entries are only deleted once no remaining entry references them.
+    fn evict_lru(&self, max_bytes: u64) -> Eviction;
+    /// Prunes entries and not-found markers selected by `filter`, then deletes bodies no entry references.
+    fn gc(&self, filter: &GcFilter<'_>) -> GcReport;
+    fn stats(&self) -> CacheStats;
+    /// Appends a run's hit and miss counters, keeping the most recent `RUN_HISTORY_LEN` runs.
+    fn record_run(&self, hits: usize, misses: usize);
+    /// Persists any state held in memory.
+    fn flush(&self) {}
+}
+
+#[derive(Clone)]
+pub(crate) struct CacheManager {
+    store: Arc<dyn Store>,
+    /// Entries fetched more recently than this are served without revalidation.
+    max_age: Option<Duration>,
+    /// Not-found markers older than this are ignored, so files added since are picked up.
+    notfound_ttl: Option<Duration>,
+    /// Shared cache read through on local misses and written back to after fetches.
+    remote: Option<Arc<RemoteCache>>,
+}
+
+impl CacheManager {
+    pub(crate) fn open(cache_dir: PathBuf, backend: CacheBackend) -> Result<Self> {
+        let store: Arc<dyn Store> = match backend {
+            CacheBackend::Files => Arc::new(files::FileStore::open(cache_dir)),
+            CacheBackend::Sqlite => Arc::new(sqlite::SqliteStore::open(&cache_dir)?),
+        };
+        Ok(Self { store, max_age: None, notfound_ttl: None, remote: None })
+    }
+
+    pub(crate) fn with_max_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    pub(crate) fn with_notfound_ttl(mut self, notfound_ttl: Duration) -> Self {
+        self.notfound_ttl = Some(notfound_ttl);
+        self
+    }
+
+    /// Returns true if the URI was recently found not to exist.
+    fn is_notfound(&self, uri: &str) -> bool {
+        self.store.notfound_since(uri).is_some_and(|marked_at| {
+            self.notfound_ttl.map_or(true, |ttl| unix_now().saturating_sub(marked_at) < ttl.as_secs())
+        })
+    }
+
+    pub(crate) fn with_remote(mut self, remote: Option<Arc<RemoteCache>>) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    pub(crate) fn remote(&self) -> Option<&RemoteCache> {
+        self.remote.as_deref()
+    }
+
+    fn is_fresh(&self, entry: &Entry) -> bool {
+        self.max_age.is_some_and(|max_age| unix_now().saturating_sub(entry.fetched_at) < max_age.as_secs())
+    }
+
+    /// Marks the entry as used now and writes it back.
+    fn touch(&self, entry: &mut Entry, body: &[u8]) {
+        entry.accessed_at = unix_now();
+        self.store.write_entry(entry, body);
+    }
+
+    /// Drops the cached entry and not-found marker for the URI so the next request fetches it unconditionally.
+    pub(crate) fn invalidate(&self, uri: &str) {
+        self.store.invalidate(uri);
+    }
+
+    /// Describes how a request for the URI would be served, without making any network calls.
+    pub(crate) fn plan(&self, uri: &str) -> &'static str {
+        if self.is_notfound(uri) {
+            return "notfound";
+        }
+        match self.store.read_entry(uri) {
+            Some(entry) if self.is_fresh(&entry) => "fresh",
+            Some(entry) if entry.etag.is_some() => "revalidate",
+            _ => "fetch",
+        }
+    }
+
+    /// Fetches a raw file through the cache.
+    pub(crate) async fn get_or_fetch(&self, uri: &str, gh_client: &GitHubClient) -> Result<FetchedFile> {
+        self.get_or_fetch_as(uri, RAW_MEDIA_TYPE, gh_client, |_| {}).await
+    }
+
+    /// Fetches `uri` through the cache, requesting the `accept` media type. `on_headers` sees the headers of the
+    /// response if a request was made.
+    pub(crate) async fn get_or_fetch_as(
+        &self,
+        uri: &str,
+        accept: &str,
+        gh_client: &GitHubClient,
+        on_headers: impl FnOnce(&HeaderMap),
+    ) -> Result<FetchedFile> {
+        if self.is_notfound(uri) {
+            gh_client.stats.cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Err(YggError::NotFound);
+        }
+
+        let mut cached = self.store.read_entry(uri);
+        let mut from_remote = false;
+        if let (None, Some(remote), false) = (&cached, &self.remote, gh_client.offline) {
+            if let Some((entry, body)) = remote.get(uri).await {
+                self.store.write_entry(&entry, &body);
+                cached = Some(entry);
+                from_remote = true;
+            }
+        }
+
+        if let Some(mut entry) = cached.as_ref().filter(|entry| self.is_fresh(entry)).cloned() {
+            gh_client.stats.cache_hits.fetch_add(1, Ordering::Relaxed);
+            let body = self.store.read_body(&entry.hash)?;
+            self.touch(&mut entry, &body);
+            return Ok(FetchedFile { body, moved: false });
+        }
+
+        let mut request_builder = gh_client.client.get(uri);
+        request_builder = request_builder.header("User-Agent", "ygg/0.1");
+        request_builder = request_builder.header("Accept", accept);
+        request_builder = request_builder.header("X-GitHub-Api-Version", "2022-11-28");
+
+        if let Some(e) = cached.as_ref().and_then(|entry| entry.etag.as_deref()) {
+            request_builder = request_builder.header("If-None-Match", e);
+        }
+
+        let res = match gh_client.send(request_builder).await {
+            // Out of live requests or offline: serve a stale copy from the cache if there is one
+            Err(YggError::BudgetExhausted | YggError::NotCached) if cached.is_some() => {
+                gh_client.stats.cache_hits.fetch_add(1, Ordering::Relaxed);
+                let mut entry = cached.unwrap();
+                let body = self.store.read_body(&entry.hash)?;
+                self.touch(&mut entry, &body);
+                return Ok(FetchedFile { body, moved: false });
+            }
+            res => res?,
+        };
+        on_headers(res.headers());
+
+        let status = res.status();
+        let moved = res.url().as_str() != uri;
+
+        let body_bytes = match cached {
+            Some(mut entry) if status == StatusCode::NOT_MODIFIED => {
+                // Use cached raw content
+                gh_client.stats.cache_hits.fetch_add(1, Ordering::Relaxed);
+                let body = self.store.read_body(&entry.hash)?;
+                entry.fetched_at = unix_now();
+                self.touch(&mut entry, &body);
+                if let Some(remote) = &self.remote {
+                    // Bodies read from the remote are already there
+                    remote.put(&entry, (!from_remote).then_some(body.as_slice()));
+                }
+                body
+            }
+            _ if status.is_success() => {
+                // Get new body and etag
+                let new_etag = res.headers().get("ETag").and_then(|v| v.to_str().ok()).map(|s| s.to_string());
+
+                let bytes = res.bytes().await?.to_vec();
+                gh_client.stats.cache_misses.fetch_add(1, Ordering::Relaxed);
+
+                // Update cache
+                let mut entry = Entry {
+                    uri: uri.to_string(),
+                    etag: new_etag,
+                    hash: content_hash(&bytes),
+                    fetched_at: unix_now(),
+                    accessed_at: 0,
+                };
+                self.touch(&mut entry, &bytes);
+                if let Some(remote) = &self.remote {
+                    remote.put(&entry, Some(&bytes));
+                }
+
+                bytes
+            }
+            _ => return Err(self.error_for(res, uri).await),
+        };
+
+        Ok(FetchedFile { body: body_bytes, moved })
+    }
+
+    pub(crate) fn evict_lru(&self, max_bytes: u64) -> Eviction {
+        self.store.evict_lru(max_bytes)
+    }
+
+    pub(crate) fn gc(&self, filter: &GcFilter<'_>) -> GcReport {
+        let report = self.store.gc(filter);
+        self.store.flush();
+        report
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.store.stats()
+    }
+
+    pub(crate) fn record_run(&self, hits: usize, misses: usize) {
+        self.store.record_run(hits, misses);
+    }
+
+    pub(crate) fn flush(&self) {
+        self.store.flush();
+    }
+
+    /// Flushes pending local writes and waits for background uploads to the remote cache, if any.
+    pub(crate) async fn finish(&self) {
+        self.flush();
+        if let Some(remote) = self.remote() {
+            remote.finish_uploads().await;
+        }
+    }
+
+    /// Maps an unsuccessful response to an error, recording a not-found marker for 404s.
+    async fn error_for(&self, res: reqwest::Response, uri: &str) -> YggError {
+        let status = res.status();
+
+        if let Some(url) = sso_authorization_url(&res) {
+            YggError::SsoRequired(url)
+        } else if status == StatusCode::NOT_FOUND {
+            self.store.mark_notfound(uri);
+            YggError::NotFound
+        } else if status == StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS || status == StatusCode::FORBIDDEN {
+            // Disabled or DMCA-blocked repos return a JSON body describing the block
+            let body: Option<ApiErrorBody> = res.json().await.ok();
+            let message = body.as_ref().and_then(|b| b.message.clone()).unwrap_or_default();
+            let too_large = body.as_ref()
+                .is_some_and(|b| b.errors.iter().any(|e| e.code.as_deref() == Some("too_large")));
+            let reason = body.and_then(|b| b.block).map(|block| block.reason.unwrap_or_default());
+            match reason {
+                None if too_large => YggError::TooLarge,
+                Some(reason) => YggError::Blocked(if reason.is_empty() { message } else { reason }),
+                None if status == StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS => YggError::Blocked(message),
+                None if message.contains("blocked") || message.contains("disabled") => YggError::Blocked(message),
+                None => YggError::UnexpectedStatus(status),
+            }
+        } else {
+            YggError::UnexpectedStatus(status)
+        }
+    }
+}
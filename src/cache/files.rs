@@ -0,0 +1,360 @@
+//! Directory-of-files cache store.
+//!
+//! Entries live under `entries/`, named by the SHA-256 of their URI so keys can neither collide nor contain
+//! characters the filesystem rejects, and bodies under `objects/{sha256}`. `index.json` maps each key back to
+//! its URI for inspection, and `runs.json` keeps hit and miss counters for recent runs.
+//!
+//! Several ygg processes may share one cache directory. Every file is written to a temporary name and renamed
+//! into place, so readers never see a partial write, and updates that touch both an entry and its not-found
+//! marker hold an exclusive lock on `locks/{first byte of the key}` so they can't interleave.
+
+use fs4::fs_std::FileExt;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use super::{
+    age_histogram, compress, content_hash, decompress, CacheStats, Entry, Eviction, GcFilter, GcReport, RunRecord,
+    Store, RUN_HISTORY_LEN,
+};
+use crate::{unix_now, Result};
+
+pub(super) struct FileStore {
+    cache_dir: PathBuf,
+    /// Entry key to URI, persisted to `index.json` on flush.
+    index: Mutex<BTreeMap<String, String>>,
+}
+
+impl FileStore {
+    pub(super) fn open(cache_dir: PathBuf) -> Self {
+        let index = fs::read(cache_dir.join("index.json"))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        let store = Self { cache_dir, index: Mutex::new(index) };
+        store.migrate_legacy_entries();
+        store
+    }
+
+    /// Returns the entry metadata and not-found marker paths used to cache the given URI.
+    fn entry_paths(&self, uri: &str) -> (PathBuf, PathBuf) {
+        let cache_key = content_hash(uri.as_bytes());
+        self.index.lock().unwrap().entry(cache_key.clone()).or_insert_with(|| uri.to_string());
+        let entries_dir = self.cache_dir.join("entries");
+        (entries_dir.join(format!("{cache_key}.json")), entries_dir.join(format!("{cache_key}.notfound")))
+    }
+
+    /// Clears out files written under the old `owner_repo_contents_path` key scheme: entries are moved to their
+    /// hashed keys, and raw bodies, `.etag` sidecars and not-found markers are dropped, since they don't record
+    /// their URI and the old scheme is ambiguous.
+    fn migrate_legacy_entries(&self) {
+        let Ok(dir) = fs::read_dir(&self.cache_dir) else {
+            return;
+        };
+        let legacy = dir
+            .filter_map(|item| item.ok().map(|item| item.path()))
+            .filter(|path| path.is_file() && path.file_name().and_then(|name| name.to_str()).is_some_and(is_legacy_name));
+        for path in legacy {
+            let entry = path
+                .extension()
+                .is_some_and(|ext| ext == "json")
+                .then(|| fs::read(&path).ok().and_then(|data| serde_json::from_slice::<Entry>(&data).ok()))
+                .flatten();
+            match entry {
+                Some(entry) => {
+                    let (entry_path, _) = self.entry_paths(&entry.uri);
+                    let _ = fs::create_dir_all(self.cache_dir.join("entries"));
+                    let _ = fs::rename(&path, entry_path);
+                }
+                None => {
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+    }
+
+    /// Takes an exclusive lock shared by all entries whose key starts with the same byte as `name`'s hash,
+    /// held until the returned file is dropped. Locking is best-effort: without it, writes are still atomic.
+    fn lock(&self, name: &str) -> Option<fs::File> {
+        let locks_dir = self.cache_dir.join("locks");
+        fs::create_dir_all(&locks_dir).ok()?;
+        let stripe = &content_hash(name.as_bytes())[..2];
+        let file = fs::OpenOptions::new().create(true).truncate(false).write(true).open(locks_dir.join(stripe)).ok()?;
+        file.lock_exclusive().ok()?;
+        Some(file)
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.cache_dir.join("objects").join(hash)
+    }
+
+    /// Loads every cache entry along with the path of its metadata file.
+    fn entries(&self) -> Vec<(PathBuf, Entry)> {
+        let Ok(dir) = fs::read_dir(self.cache_dir.join("entries")) else {
+            return Vec::new();
+        };
+        dir.filter_map(|item| item.ok().map(|item| item.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|path| {
+                let entry = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+                Some((path, entry))
+            })
+            .collect()
+    }
+
+    fn load_runs(&self) -> Vec<RunRecord> {
+        fs::read(self.cache_dir.join("runs.json"))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Returns true for the name of a file the old key scheme wrote to the top of the cache directory. Every URI it
+/// cached was a repository's contents or blob, so their names all contain one of those path segments.
+fn is_legacy_name(name: &str) -> bool {
+    name.contains("_contents_") || name.contains("_git_blobs_")
+}
+
+/// Writes `data` to a temporary file next to `path` and renames it into place, so concurrent readers see either
+/// the old or the new contents and never a partial write.
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(
+        ".{file_name}.{}.{}.tmp",
+        std::process::id(),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        e
+    })
+}
+
+/// Reads when a not-found marker was written: its contents, or its modification time for markers written before
+/// they recorded a timestamp.
+fn marked_at(path: &Path) -> Option<u64> {
+    let contents = fs::read_to_string(path).ok()?;
+    contents.trim().parse().ok().or_else(|| {
+        let modified = fs::metadata(path).ok()?.modified().ok()?;
+        Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+    })
+}
+
+impl Store for FileStore {
+    fn notfound_since(&self, uri: &str) -> Option<u64> {
+        marked_at(&self.entry_paths(uri).1)
+    }
+
+    fn mark_notfound(&self, uri: &str) {
+        let (entry_path, notfound_path) = self.entry_paths(uri);
+        let _lock = self.lock(uri);
+        let _ = fs::remove_file(entry_path);
+        let _ = write_atomic(&notfound_path, unix_now().to_string().as_bytes());
+    }
+
+    fn read_entry(&self, uri: &str) -> Option<Entry> {
+        let data = fs::read(self.entry_paths(uri).0).ok()?;
+        let entry: Entry = serde_json::from_slice(&data).ok()?;
+        // An entry whose object has gone missing is as good as no entry
+        self.object_path(&entry.hash).exists().then_some(entry)
+    }
+
+    fn read_body(&self, hash: &str) -> Result<Vec<u8>> {
+        decompress(fs::read(self.object_path(hash))?)
+    }
+
+    fn write_entry(&self, entry: &Entry, body: &[u8]) {
+        // Objects are content-addressed, so concurrent writers of the same object write identical bytes
+        let object_path = self.object_path(&entry.hash);
+        if !object_path.exists() {
+            if let Some(compressed) = compress(body) {
+                let _ = write_atomic(&object_path, &compressed);
+            }
+        }
+        let (entry_path, notfound_path) = self.entry_paths(&entry.uri);
+        let _lock = self.lock(&entry.uri);
+        let _ = fs::remove_file(notfound_path);
+        if let Ok(data) = serde_json::to_vec(entry) {
+            let _ = write_atomic(&entry_path, &data);
+        }
+    }
+
+    fn invalidate(&self, uri: &str) {
+        let (entry_path, notfound_path) = self.entry_paths(uri);
+        let _lock = self.lock(uri);
+        let _ = fs::remove_file(entry_path);
+        let _ = fs::remove_file(notfound_path);
+    }
+
+    fn evict_lru(&self, max_bytes: u64) -> Eviction {
+        let mut entries = self.entries();
+        entries.sort_by_key(|(_, entry)| entry.accessed_at.max(entry.fetched_at));
+
+        let mut references: HashMap<String, usize> = HashMap::new();
+        for (_, entry) in &entries {
+            *references.entry(entry.hash.clone()).or_default() += 1;
+        }
+        let object_size = |hash: &str| fs::metadata(self.object_path(hash)).map(|m| m.len()).unwrap_or(0);
+        let mut total: u64 = references.keys().map(|hash| object_size(hash)).sum();
+
+        let mut eviction = Eviction::default();
+        for (path, entry) in entries {
+            if total <= max_bytes {
+                break;
+            }
+            let _ = fs::remove_file(&path);
+            eviction.entries += 1;
+
+            let count = references.get_mut(&entry.hash).expect("every entry is counted");
+            *count -= 1;
+            if *count == 0 {
+                let size = object_size(&entry.hash);
+                let _ = fs::remove_file(self.object_path(&entry.hash));
+                total = total.saturating_sub(size);
+                eviction.bytes += size;
+            }
+        }
+        eviction
+    }
+
+    fn gc(&self, filter: &GcFilter<'_>) -> GcReport {
+        let mut report = GcReport::default();
+
+        let now = unix_now();
+        for (path, entry) in self.entries() {
+            let orphaned = filter.orphans && !self.object_path(&entry.hash).exists();
+            if orphaned || filter.selects(Some(&entry.uri), now.saturating_sub(entry.fetched_at)) {
+                let _ = fs::remove_file(&path);
+                report.entries += 1;
+            }
+        }
+
+        let index = self.index.lock().unwrap().clone();
+        let markers = fs::read_dir(self.cache_dir.join("entries")).into_iter().flatten().filter_map(|item| item.ok());
+        for item in markers.filter(|item| item.path().extension().is_some_and(|ext| ext == "notfound")) {
+            let path = item.path();
+            let uri = path.file_stem().and_then(|key| index.get(key.to_str()?));
+            let age = marked_at(&path).map_or(0, |marked_at| now.saturating_sub(marked_at));
+            let orphaned = filter.orphans && uri.is_none();
+            if orphaned || filter.selects(uri.map(String::as_str), age) {
+                let _ = fs::remove_file(&path);
+                report.notfound_markers += 1;
+            }
+        }
+
+        let referenced: HashSet<String> = self.entries().into_iter().map(|(_, entry)| entry.hash).collect();
+        let objects = fs::read_dir(self.cache_dir.join("objects")).into_iter().flatten().filter_map(|item| item.ok());
+        // Skip temporary files, which may be another process's write in flight
+        for item in objects.filter(|item| !item.file_name().to_string_lossy().starts_with('.')) {
+            if !referenced.contains(item.file_name().to_string_lossy().as_ref()) {
+                report.bytes += item.metadata().map(|m| m.len()).unwrap_or(0);
+                let _ = fs::remove_file(item.path());
+                report.objects += 1;
+            }
+        }
+
+        report
+    }
+
+    fn stats(&self) -> CacheStats {
+        let entries = self.entries();
+        let ages = age_histogram(entries.iter().map(|(_, entry)| entry.fetched_at));
+
+        let count_files = |dir: PathBuf, extension: Option<&str>| -> (usize, u64) {
+            let Ok(dir) = fs::read_dir(dir) else {
+                return (0, 0);
+            };
+            dir.filter_map(|item| item.ok())
+                .filter(|item| extension.map_or(true, |ext| item.path().extension().is_some_and(|e| e == ext)))
+                .fold((0, 0), |(count, bytes), item| {
+                    (count + 1, bytes + item.metadata().map(|m| m.len()).unwrap_or(0))
+                })
+        };
+        let (notfound_markers, _) = count_files(self.cache_dir.join("entries"), Some("notfound"));
+        let (objects, object_bytes) = count_files(self.cache_dir.join("objects"), None);
+
+        CacheStats { entries: entries.len(), notfound_markers, objects, object_bytes, ages, runs: self.load_runs() }
+    }
+
+    fn record_run(&self, hits: usize, misses: usize) {
+        let _lock = self.lock("runs.json");
+        let mut runs = self.load_runs();
+        runs.push(RunRecord { finished_at: unix_now(), hits, misses });
+        let excess = runs.len().saturating_sub(RUN_HISTORY_LEN);
+        runs.drain(..excess);
+        if let Ok(data) = serde_json::to_vec_pretty(&runs) {
+            let _ = write_atomic(&self.cache_dir.join("runs.json"), &data);
+        }
+    }
+
+    /// Writes the key-to-URI index, merged with keys other processes have written since it was loaded, and
+    /// dropping keys that no longer have an entry or not-found marker.
+    fn flush(&self) {
+        let _lock = self.lock("index.json");
+        let mut index = self.index.lock().unwrap();
+        let on_disk: BTreeMap<String, String> = fs::read(self.cache_dir.join("index.json"))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        for (key, uri) in on_disk {
+            index.entry(key).or_insert(uri);
+        }
+        let entries_dir = self.cache_dir.join("entries");
+        index.retain(|key, _| {
+            entries_dir.join(format!("{key}.json")).exists() || entries_dir.join(format!("{key}.notfound")).exists()
+        });
+        if let Ok(data) = serde_json::to_vec_pretty(&*index) {
+            let _ = write_atomic(&self.cache_dir.join("index.json"), &data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_legacy_layout() {
+        let cache_dir = std::env::temp_dir().join(format!("ygg-legacy-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&cache_dir);
+        fs::create_dir_all(&cache_dir).unwrap();
+        let uri = "https://api.github.com/repos/acme/api/contents/package-lock.json";
+        let entry = Entry { uri: uri.to_string(), etag: None, hash: "0".repeat(64), fetched_at: 1, accessed_at: 1 };
+        let files = [
+            // Raw bodies and ETag sidecars from before entries were introduced
+            ("acme_api_contents_package-lock.json", b"{\"lockfileVersion\": 3}".to_vec()),
+            ("acme_api_contents_package-lock.json.etag", b"\"abc\"".to_vec()),
+            ("acme_web_contents_Dockerfile", b"FROM node:20".to_vec()),
+            ("acme_web_contents_Dockerfile.etag", b"\"def\"".to_vec()),
+            ("acme_old_contents_package-lock.json.notfound", Vec::new()),
+            ("acme_api_git_blobs_0123abcd", b"{}".to_vec()),
+            // An entry from before they were keyed by hash
+            ("acme_api_contents_package-lock.json?ref=main.json", serde_json::to_vec(&entry).unwrap()),
+            // Files the current layout keeps at the top
+            ("runs.json", b"[]".to_vec()),
+            ("history.sqlite3", Vec::new()),
+        ];
+        for (name, data) in &files {
+            fs::write(cache_dir.join(name), data).unwrap();
+        }
+
+        let store = FileStore::open(cache_dir.clone());
+        let mut left: Vec<String> =
+            fs::read_dir(&cache_dir).unwrap().filter_map(|item| item.ok()?.file_name().into_string().ok()).collect();
+        left.sort();
+        assert_eq!(left, ["entries", "history.sqlite3", "runs.json"]);
+        let (entry_path, _) = store.entry_paths(uri);
+        assert!(entry_path.exists());
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
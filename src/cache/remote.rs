@@ -0,0 +1,278 @@
+//! Shared remote cache in an S3 bucket (or any S3-compatible store, such as GCS with HMAC keys or MinIO).
+//!
+//! The remote mirrors the local `files` layout: `{prefix}/entries/{sha256(uri)}.json` and compressed bodies at
+//! `{prefix}/objects/{sha256}`. Local misses are read through from the remote, and new or revalidated entries
+//! are written back in the background so ephemeral CI runners and developer machines share one warm cache.
+//!
+//! The same client uploads published reports (`--publish s3://...`).
+//!
+//! Credentials and region come from the standard `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`,
+//! `AWS_SESSION_TOKEN` and `AWS_REGION` variables; `AWS_ENDPOINT_URL` selects a non-AWS endpoint.
+
+use chrono::DateTime;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, StatusCode};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+use super::{compress, content_hash, decompress, Entry};
+use crate::{unix_now, NetworkOptions, Result, YggError};
+
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// Timeout for each request to the remote cache. A slow remote should never be slower than GitHub itself.
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Credentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+/// Counters reported after a run.
+#[derive(Default)]
+pub(crate) struct RemoteStats {
+    pub(crate) downloaded: AtomicUsize,
+    pub(crate) uploaded: AtomicUsize,
+    pub(crate) failed: AtomicUsize,
+}
+
+pub(crate) struct RemoteCache {
+    client: Client,
+    /// Base URL objects are addressed under, without a trailing slash.
+    base_url: String,
+    /// Path of the bucket within `base_url` as signed, e.g. "/bucket" for path-style addressing.
+    base_path: String,
+    host: String,
+    prefix: String,
+    region: String,
+    credentials: Credentials,
+    /// Uploads still in flight, waited for by `finish_uploads`.
+    uploads: Mutex<Vec<JoinHandle<()>>>,
+    pub(crate) stats: RemoteStats,
+}
+
+impl RemoteCache {
+    /// Connects to a remote cache given as `s3://bucket[/prefix]`.
+    pub(crate) fn open(location: &str, network: &NetworkOptions) -> Result<Arc<Self>> {
+        let invalid = || YggError::Config(format!("remote cache must look like s3://bucket/prefix, got {location:?}"));
+        let rest = location.strip_prefix("s3://").ok_or_else(invalid)?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(invalid());
+        }
+
+        let credentials = Credentials {
+            access_key: env::var("AWS_ACCESS_KEY_ID")?,
+            secret_key: env::var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: env::var("AWS_SESSION_TOKEN").ok().filter(|token| !token.is_empty()),
+        };
+        let region = env::var("AWS_REGION")
+            .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| DEFAULT_REGION.to_string());
+
+        // AWS buckets are addressed virtual-hosted style; custom endpoints generally only support path-style
+        let (base_url, base_path) = match env::var("AWS_ENDPOINT_URL_S3").or_else(|_| env::var("AWS_ENDPOINT_URL")) {
+            Ok(endpoint) => {
+                let endpoint = endpoint.trim_end_matches('/');
+                (format!("{endpoint}/{bucket}"), format!("/{}", uri_encode(bucket)))
+            }
+            Err(_) => (format!("https://{bucket}.s3.{region}.amazonaws.com"), String::new()),
+        };
+        let host = reqwest::Url::parse(&base_url)
+            .ok()
+            .and_then(|url| Some(match url.port() {
+                Some(port) => format!("{}:{port}", url.host_str()?),
+                None => url.host_str()?.to_string(),
+            }))
+            .ok_or_else(invalid)?;
+
+        Ok(Arc::new(Self {
+            client: network.client_builder()?.timeout(REMOTE_TIMEOUT).build()?,
+            base_url,
+            base_path,
+            host,
+            prefix: prefix.trim_matches('/').to_string(),
+            region,
+            credentials,
+            uploads: Mutex::default(),
+            stats: RemoteStats::default(),
+        }))
+    }
+
+    fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{path}", self.prefix)
+        }
+    }
+
+    /// Looks the URI up in the remote, returning its entry and decompressed body.
+    pub(super) async fn get(&self, uri: &str) -> Option<(Entry, Vec<u8>)> {
+        let key = content_hash(uri.as_bytes());
+        let entry: Entry = serde_json::from_slice(&self.get_object(&format!("entries/{key}.json")).await?).ok()?;
+        let body = decompress(self.get_object(&format!("objects/{}", entry.hash)).await?).ok()?;
+        // Guard against a corrupt or mismatched upload poisoning every runner's cache
+        if entry.uri != uri || content_hash(&body) != entry.hash {
+            return None;
+        }
+        self.stats.downloaded.fetch_add(1, Ordering::Relaxed);
+        Some((entry, body))
+    }
+
+    /// Uploads the entry in the background, along with its body when `body` is given.
+    pub(super) fn put(self: &Arc<Self>, entry: &Entry, body: Option<&[u8]>) {
+        let Ok(entry_json) = serde_json::to_vec(entry) else {
+            return;
+        };
+        let key = content_hash(entry.uri.as_bytes());
+        let object = body.and_then(compress).map(|compressed| (format!("objects/{}", entry.hash), compressed));
+        let remote = Arc::clone(self);
+        let upload = tokio::spawn(async move {
+            // The body goes first so a reader never sees an entry whose body is missing
+            let mut ok = true;
+            if let Some((path, compressed)) = object {
+                ok = remote.put_object(&path, compressed).await;
+            }
+            ok = ok && remote.put_object(&format!("entries/{key}.json"), entry_json).await;
+            let counter = if ok { &remote.stats.uploaded } else { &remote.stats.failed };
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let mut uploads = self.uploads.lock().unwrap();
+        // Long-lived processes (ygg serve) only wait for uploads at shutdown, so finished ones are dropped here
+        uploads.retain(|upload| !upload.is_finished());
+        uploads.push(upload);
+    }
+
+    /// Waits for background uploads to finish.
+    pub(crate) async fn finish_uploads(&self) {
+        let uploads = std::mem::take(&mut *self.uploads.lock().unwrap());
+        for upload in uploads {
+            let _ = upload.await;
+        }
+    }
+
+    async fn get_object(&self, path: &str) -> Option<Vec<u8>> {
+        let res = self.signed(Method::GET, path, &[]).send().await;
+        match res {
+            Ok(res) if res.status() == StatusCode::OK => res.bytes().await.ok().map(|bytes| bytes.to_vec()),
+            Ok(res) if res.status() == StatusCode::NOT_FOUND => None,
+            _ => {
+                self.stats.failed.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    async fn put_object(&self, path: &str, body: Vec<u8>) -> bool {
+        let request = self.signed(Method::PUT, path, &body).body(body);
+        matches!(request.send().await, Ok(res) if res.status().is_success())
+    }
+
+    /// Uploads `body` to `path` under the prefix, returning the error the store responded with.
+    pub(crate) async fn upload(&self, path: &str, body: Vec<u8>, content_type: &str) -> Result<()> {
+        let resp = self.signed(Method::PUT, path, &body).header(reqwest::header::CONTENT_TYPE, content_type).body(body).send().await?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            return Err(YggError::ApiError(format!("uploading {} responded {status}: {}", self.key(path), body.trim())));
+        }
+        Ok(())
+    }
+
+    /// Builds a request signed with AWS Signature Version 4.
+    fn signed(&self, method: Method, path: &str, body: &[u8]) -> reqwest::RequestBuilder {
+        let key = self.key(path);
+        let encoded_key: Vec<String> = key.split('/').map(uri_encode).collect();
+        let encoded_key = encoded_key.join("/");
+        let (amz_date, date) = amz_timestamps(unix_now());
+        let payload_hash = format!("{:x}", Sha256::digest(body));
+
+        let mut headers = vec![
+            ("host", self.host.clone()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{name}:{value}\n")).collect();
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+
+        let canonical_request = format!(
+            "{method}\n{}/{encoded_key}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+            self.base_path
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign =
+            format!("AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{:x}", Sha256::digest(canonical_request.as_bytes()));
+
+        let mut signing_key = format!("AWS4{}", self.credentials.secret_key).into_bytes();
+        for part in [date.as_str(), self.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac_sha256(&signing_key, part.as_bytes());
+        }
+        let signature: String =
+            hmac_sha256(&signing_key, string_to_sign.as_bytes()).iter().map(|b| format!("{b:02x}")).collect();
+
+        let mut request = self.client.request(method, format!("{}/{encoded_key}", self.base_url)).header(
+            "Authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                self.credentials.access_key
+            ),
+        );
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+        request
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes a path segment as SigV4 requires: everything except unreserved characters.
+fn uri_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Formats a Unix timestamp as SigV4's `YYYYMMDDTHHMMSSZ` timestamp and `YYYYMMDD` date.
+fn amz_timestamps(unix: u64) -> (String, String) {
+    let time = DateTime::from_timestamp(unix as i64, 0).unwrap_or_default();
+    (time.format("%Y%m%dT%H%M%SZ").to_string(), time.format("%Y%m%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_encode_keeps_only_unreserved_characters() {
+        assert_eq!(uri_encode("AZaz09-_.~"), "AZaz09-_.~");
+        assert_eq!(uri_encode("a b+c/d"), "a%20b%2Bc%2Fd");
+        assert_eq!(uri_encode("café"), "caf%C3%A9");
+    }
+
+    #[test]
+    fn amz_timestamps_format_utc() {
+        assert_eq!(amz_timestamps(0), ("19700101T000000Z".to_string(), "19700101".to_string()));
+        // From the SigV4 examples in the AWS documentation
+        assert_eq!(amz_timestamps(1_369_353_600), ("20130524T000000Z".to_string(), "20130524".to_string()));
+        // A leap day, one second before midnight
+        assert_eq!(amz_timestamps(1_709_251_199), ("20240229T235959Z".to_string(), "20240229".to_string()));
+    }
+}
@@ -0,0 +1,244 @@
+//! SQLite cache store.
+//!
+//! Keeps entries, compressed bodies, not-found markers and run counters in a single `cache.sqlite3` database,
+//! which holds up better than a directory of files at tens of thousands of entries.
+
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::{
+    age_histogram, compress, decompress, CacheStats, Entry, Eviction, GcFilter, GcReport, RunRecord, Store,
+    RUN_HISTORY_LEN,
+};
+use crate::{unix_now, Result};
+
+const DATABASE_FILE: &str = "cache.sqlite3";
+
+/// How long to wait for another process's write transaction before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+    CREATE TABLE IF NOT EXISTS entries (
+        uri TEXT PRIMARY KEY,
+        etag TEXT,
+        hash TEXT NOT NULL,
+        fetched_at INTEGER NOT NULL,
+        accessed_at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS entries_hash ON entries (hash);
+    CREATE TABLE IF NOT EXISTS objects (hash TEXT PRIMARY KEY, body BLOB NOT NULL);
+    CREATE TABLE IF NOT EXISTS notfound (uri TEXT PRIMARY KEY, marked_at INTEGER NOT NULL);
+    CREATE TABLE IF NOT EXISTS runs (finished_at INTEGER NOT NULL, hits INTEGER NOT NULL, misses INTEGER NOT NULL);
+    -- Results now go to the run history (history.sqlite3)
+    DROP TABLE IF EXISTS results;
+";
+
+pub(super) struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub(super) fn open(cache_dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(cache_dir)?;
+        let conn = Connection::open(cache_dir.join(DATABASE_FILE))?;
+        // Other ygg processes sharing the cache may hold the write lock briefly
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    fn load_runs(&self) -> Vec<RunRecord> {
+        let conn = self.conn.lock().unwrap();
+        let runs = conn
+            .prepare("SELECT finished_at, hits, misses FROM runs ORDER BY finished_at")
+            .and_then(|mut stmt| {
+                stmt.query_map([], |row| Ok(RunRecord { finished_at: row.get(0)?, hits: row.get(1)?, misses: row.get(2)? }))?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            });
+        runs.unwrap_or_default()
+    }
+
+    /// Deletes stored bodies no entry references, returning how many were deleted and their total size.
+    fn delete_unreferenced_objects(conn: &Connection) -> rusqlite::Result<(usize, u64)> {
+        let unreferenced = "FROM objects WHERE hash NOT IN (SELECT hash FROM entries)";
+        let counts = conn.query_row(&format!("SELECT COUNT(*), COALESCE(SUM(LENGTH(body)), 0) {unreferenced}"), [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        conn.execute(&format!("DELETE {unreferenced}"), [])?;
+        Ok(counts)
+    }
+}
+
+impl Store for SqliteStore {
+    fn notfound_since(&self, uri: &str) -> Option<u64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT marked_at FROM notfound WHERE uri = ?1", [uri], |row| row.get(0)).optional().ok().flatten()
+    }
+
+    fn mark_notfound(&self, uri: &str) {
+        let conn = self.conn.lock().unwrap();
+        let _ = conn.execute("DELETE FROM entries WHERE uri = ?1", [uri]);
+        let _ = conn.execute("INSERT OR REPLACE INTO notfound (uri, marked_at) VALUES (?1, ?2)", params![uri, unix_now()]);
+    }
+
+    fn read_entry(&self, uri: &str) -> Option<Entry> {
+        let conn = self.conn.lock().unwrap();
+        // An entry whose body has gone missing is as good as no entry
+        conn.query_row(
+            "SELECT uri, etag, hash, fetched_at, accessed_at FROM entries
+             WHERE uri = ?1 AND hash IN (SELECT hash FROM objects)",
+            [uri],
+            |row| {
+                Ok(Entry {
+                    uri: row.get(0)?,
+                    etag: row.get(1)?,
+                    hash: row.get(2)?,
+                    fetched_at: row.get(3)?,
+                    accessed_at: row.get(4)?,
+                })
+            },
+        )
+        .optional()
+        .ok()
+        .flatten()
+    }
+
+    fn read_body(&self, hash: &str) -> Result<Vec<u8>> {
+        let data: Vec<u8> = self.conn.lock().unwrap().query_row("SELECT body FROM objects WHERE hash = ?1", [hash], |row| row.get(0))?;
+        decompress(data)
+    }
+
+    fn write_entry(&self, entry: &Entry, body: &[u8]) {
+        let conn = self.conn.lock().unwrap();
+        let stored = conn.query_row("SELECT 1 FROM objects WHERE hash = ?1", [&entry.hash], |_| Ok(())).optional();
+        if let (Ok(None), Some(compressed)) = (stored, compress(body)) {
+            let _ = conn.execute("INSERT OR IGNORE INTO objects (hash, body) VALUES (?1, ?2)", params![entry.hash, compressed]);
+        }
+        let _ = conn.execute(
+            "INSERT OR REPLACE INTO entries (uri, etag, hash, fetched_at, accessed_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![entry.uri, entry.etag, entry.hash, entry.fetched_at, entry.accessed_at],
+        );
+        let _ = conn.execute("DELETE FROM notfound WHERE uri = ?1", [&entry.uri]);
+    }
+
+    fn invalidate(&self, uri: &str) {
+        let conn = self.conn.lock().unwrap();
+        let _ = conn.execute("DELETE FROM entries WHERE uri = ?1", [uri]);
+        let _ = conn.execute("DELETE FROM notfound WHERE uri = ?1", [uri]);
+    }
+
+    fn evict_lru(&self, max_bytes: u64) -> Eviction {
+        let mut conn = self.conn.lock().unwrap();
+        let evict = |conn: &mut Connection| -> rusqlite::Result<Eviction> {
+            let tx = conn.transaction()?;
+            let entries: Vec<(String, String)> = tx
+                .prepare("SELECT uri, hash FROM entries ORDER BY MAX(accessed_at, fetched_at)")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            let mut objects: HashMap<String, (usize, u64)> = tx
+                .prepare(
+                    "SELECT o.hash, COUNT(*), LENGTH(o.body) FROM objects o JOIN entries e ON e.hash = o.hash
+                     GROUP BY o.hash",
+                )?
+                .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+                .collect::<rusqlite::Result<_>>()?;
+            let mut total: u64 = objects.values().map(|&(_, size)| size).sum();
+
+            let mut eviction = Eviction::default();
+            for (uri, hash) in entries {
+                if total <= max_bytes {
+                    break;
+                }
+                tx.execute("DELETE FROM entries WHERE uri = ?1", [&uri])?;
+                eviction.entries += 1;
+
+                if let Some((count, size)) = objects.get_mut(&hash) {
+                    *count -= 1;
+                    if *count == 0 {
+                        tx.execute("DELETE FROM objects WHERE hash = ?1", [&hash])?;
+                        total = total.saturating_sub(*size);
+                        eviction.bytes += *size;
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok(eviction)
+        };
+        evict(&mut conn).unwrap_or_default()
+    }
+
+    fn gc(&self, filter: &GcFilter<'_>) -> GcReport {
+        let mut conn = self.conn.lock().unwrap();
+        let gc = |conn: &mut Connection| -> rusqlite::Result<GcReport> {
+            let tx = conn.transaction()?;
+            let now = unix_now();
+            let mut report = GcReport::default();
+
+            let entries: Vec<(String, u64, bool)> = tx
+                .prepare("SELECT uri, fetched_at, hash IN (SELECT hash FROM objects) FROM entries")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            for (uri, fetched_at, has_body) in entries {
+                let orphaned = filter.orphans && !has_body;
+                if orphaned || filter.selects(Some(&uri), now.saturating_sub(fetched_at)) {
+                    tx.execute("DELETE FROM entries WHERE uri = ?1", [&uri])?;
+                    report.entries += 1;
+                }
+            }
+
+            // Not-found markers always record their URI, so none are orphaned
+            let markers: Vec<(String, u64)> = tx
+                .prepare("SELECT uri, marked_at FROM notfound")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            for (uri, marked_at) in markers {
+                if filter.selects(Some(&uri), now.saturating_sub(marked_at)) {
+                    tx.execute("DELETE FROM notfound WHERE uri = ?1", [&uri])?;
+                    report.notfound_markers += 1;
+                }
+            }
+
+            (report.objects, report.bytes) = Self::delete_unreferenced_objects(&tx)?;
+            tx.commit()?;
+            Ok(report)
+        };
+        let report = gc(&mut conn).unwrap_or_default();
+        // Reclaim the freed pages; the database file otherwise never shrinks
+        let _ = conn.execute_batch("VACUUM");
+        report
+    }
+
+    fn stats(&self) -> CacheStats {
+        let stats = {
+            let conn = self.conn.lock().unwrap();
+            let count = |sql: &str| conn.query_row(sql, [], |row| row.get::<_, i64>(0)).unwrap_or(0);
+            let fetched_at: Vec<u64> = conn
+                .prepare("SELECT fetched_at FROM entries")
+                .and_then(|mut stmt| stmt.query_map([], |row| row.get(0))?.collect())
+                .unwrap_or_default();
+            CacheStats {
+                entries: fetched_at.len(),
+                notfound_markers: count("SELECT COUNT(*) FROM notfound") as usize,
+                objects: count("SELECT COUNT(*) FROM objects") as usize,
+                object_bytes: count("SELECT COALESCE(SUM(LENGTH(body)), 0) FROM objects") as u64,
+                ages: age_histogram(fetched_at.into_iter()),
+                runs: Vec::new(),
+            }
+        };
+        CacheStats { runs: self.load_runs(), ..stats }
+    }
+
+    fn record_run(&self, hits: usize, misses: usize) {
+        let conn = self.conn.lock().unwrap();
+        let _ = conn.execute("INSERT INTO runs (finished_at, hits, misses) VALUES (?1, ?2, ?3)", params![unix_now(), hits, misses]);
+        let _ = conn.execute(
+            "DELETE FROM runs WHERE rowid NOT IN (SELECT rowid FROM runs ORDER BY finished_at DESC LIMIT ?1)",
+            [RUN_HISTORY_LEN],
+        );
+    }
+}
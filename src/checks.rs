@@ -0,0 +1,97 @@
+//! Check runs for findings (`ygg report --check-runs`).
+//!
+//! Creates a completed check run on the head commit of each affected repository's default branch, so the
+//! finding shows up in the repository's commit status without an issue being filed. A version with advisories
+//! fails the check; one that is only below the fix version is neutral. GitHub only lets GitHub Apps create check
+//! runs, so this needs app authentication.
+
+use reqwest::Method;
+use semver::Version;
+use serde_json::json;
+
+use crate::findings::{self, Finding};
+use crate::{api_url, pins, GitHubClient, Result, YggError};
+
+/// Returns the check run's conclusion, title and summary for a finding.
+fn check_output(package: &str, filename: &str, finding: &Finding) -> (&'static str, String, String) {
+    let conclusion = if finding.advisories.is_empty() { "neutral" } else { "failure" };
+    let title = match (&finding.fix_version, finding.advisories.len()) {
+        (Some(fix), 0) => format!("{package} {} is below {fix}", finding.version),
+        (Some(fix), count) => format!("{package} {} has {count} advisories; update to {fix}", finding.version),
+        (None, count) => format!("{package} {} has {count} advisories", finding.version),
+    };
+    let mut summary = format!("A dependency audit found **{package}@{}** in `{filename}`.\n", finding.version);
+    if !finding.advisories.is_empty() {
+        summary.push('\n');
+        summary.push_str(&findings::advisory_list(&finding.advisories));
+    }
+    if let Some(fix) = &finding.fix_version {
+        summary.push_str(&format!("\nUpdate `{package}` to `{fix}` or later and regenerate the lockfile.\n"));
+    }
+    (conclusion, title, summary)
+}
+
+/// Creates the check run for a finding on the head commit of the repository's default branch and returns its URL.
+async fn create_check_run(gh_client: &GitHubClient, name: &str, package: &str, filename: &str, finding: &Finding) -> Result<String> {
+    let head_sha = pins::resolve_sha(gh_client, &finding.repo, None).await?;
+    let (conclusion, title, summary) = check_output(package, filename, finding);
+    let payload = json!({
+        "name": name,
+        "head_sha": head_sha,
+        "status": "completed",
+        "conclusion": conclusion,
+        "output": { "title": title, "summary": summary },
+    });
+    let url = format!("{}/repos/{}/check-runs", api_url(), finding.repo);
+    let resp = gh_client.send(gh_client.api_request(Method::POST, &url).json(&payload)).await?;
+    if resp.status() == reqwest::StatusCode::FORBIDDEN {
+        return Err(YggError::ApiError("the app lacks the checks:write permission".to_string()));
+    }
+    if !resp.status().is_success() {
+        return Err(YggError::UnexpectedStatus(resp.status()));
+    }
+    let check_run: serde_json::Value = resp.json().await?;
+    Ok(check_run["html_url"].as_str().unwrap_or_default().to_string())
+}
+
+/// Creates a check run in every repository the last run found an affected version of its package in. A version
+/// is affected if it has advisories, or is below `fix_version` when one is given. With `dry_run`, prints the
+/// conclusions instead.
+pub(crate) async fn create_check_runs(gh_client: &GitHubClient, fix_version: Option<&Version>, dry_run: bool) -> Result<()> {
+    if !dry_run && !gh_client.auth.is_app(0) {
+        return Err(YggError::Config(
+            "only GitHub Apps can create check runs; authenticate as an app (GH_APP_ID, GH_APP_PRIVATE_KEY_PATH)".to_string(),
+        ));
+    }
+    let findings::Findings { package, filename, findings } = findings::load(gh_client, fix_version).await?;
+    let package = package.as_str();
+    if findings.is_empty() {
+        eprintln!("No affected repositories in the last audit of {package}; no check runs to create.");
+        return Ok(());
+    }
+
+    let name = format!("ygg / {package}");
+    let (mut created, mut failed) = (0, 0);
+    for finding in &findings {
+        if dry_run {
+            let (conclusion, title, _) = check_output(package, &filename, finding);
+            println!("{}: {conclusion}: {title}", finding.repo);
+            continue;
+        }
+        match create_check_run(gh_client, &name, package, &filename, finding).await {
+            Ok(url) => {
+                created += 1;
+                println!("checked    {url}");
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {e}", finding.repo);
+            }
+        }
+    }
+
+    if !dry_run {
+        eprintln!("\n{created} check runs created, {failed} failed");
+    }
+    Ok(())
+}
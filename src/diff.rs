@@ -0,0 +1,162 @@
+//! Changes between two runs: repositories where the package (or search match) newly appeared, was upgraded or
+//! downgraded, or disappeared. Reported between runs of --watch, against a --baseline, and by `ygg diff`.
+//!
+//! Only settled outcomes count: a repository that failed in either run is left out rather than reported as
+//! appearing or disappearing.
+
+use semver::Version;
+use std::cmp::Ordering;
+use std::path::Path;
+
+use crate::{RepoState, Result, RunState, YggError};
+
+/// A repository whose result differs between two runs.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Change<'a> {
+    Appeared { repo: &'a str, value: &'a str },
+    Upgraded { repo: &'a str, from: &'a str, to: &'a str },
+    Downgraded { repo: &'a str, from: &'a str, to: &'a str },
+    /// A change between values that aren't versions.
+    Changed { repo: &'a str, from: &'a str, to: &'a str },
+    Disappeared { repo: &'a str, value: &'a str },
+}
+
+/// Returns the value a settled outcome found: Some(None) if it found nothing, None if the repository failed or
+/// wasn't processed.
+fn settled<'a>(state: &'a RunState, repo: &str) -> Option<Option<&'a str>> {
+    match state.repos.get(repo)? {
+        RepoState::Completed { value } if value != "-------" => Some(Some(value)),
+        RepoState::Completed { .. } | RepoState::NotFound => Some(None),
+        RepoState::Blocked { .. } | RepoState::Failed { .. } => None,
+    }
+}
+
+/// Returns the changes from `old` to `new`, by repository name.
+pub(crate) fn changes<'a>(old: &'a RunState, new: &'a RunState) -> Vec<Change<'a>> {
+    let mut repos: Vec<&str> = old.repos.keys().chain(new.repos.keys()).map(String::as_str).collect();
+    repos.sort_unstable();
+    repos.dedup();
+    repos
+        .into_iter()
+        .filter_map(|repo| match (settled(old, repo)?, settled(new, repo)?) {
+            (None, Some(value)) => Some(Change::Appeared { repo, value }),
+            (Some(value), None) => Some(Change::Disappeared { repo, value }),
+            (Some(from), Some(to)) if from != to => Some(match (Version::parse(from), Version::parse(to)) {
+                (Ok(a), Ok(b)) if a.cmp(&b) == Ordering::Less => Change::Upgraded { repo, from, to },
+                (Ok(_), Ok(_)) => Change::Downgraded { repo, from, to },
+                _ => Change::Changed { repo, from, to },
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Prints one line per change: "+" appeared, "^" upgraded, "v" downgraded, "~" changed, "-" disappeared.
+pub(crate) fn print_changes(changes: &[Change<'_>]) {
+    for change in changes {
+        match change {
+            Change::Appeared { repo, value } => println!("+ {repo}: {value}"),
+            Change::Upgraded { repo, from, to } => println!("^ {repo}: {from} -> {to}"),
+            Change::Downgraded { repo, from, to } => println!("v {repo}: {from} -> {to}"),
+            Change::Changed { repo, from, to } => println!("~ {repo}: {from} -> {to}"),
+            Change::Disappeared { repo, value } => println!("- {repo}: {value}"),
+        }
+    }
+}
+
+/// Reads the results of a run: ".ygg-run.json", a job's output, or results saved from --post-results.
+pub(crate) fn load(path: &Path) -> Result<RunState> {
+    let data = std::fs::read_to_string(path).map_err(|e| YggError::Config(format!("can't read {}: {e}", path.display())))?;
+    serde_json::from_str(&data).map_err(|e| YggError::Config(format!("{} is not the results of a run: {e}", path.display())))
+}
+
+/// Checks that `old` looked for `query` in `filename` too, so changes since it mean something.
+pub(crate) fn check_comparable(old: &RunState, query: &str, filename: &str) -> Result<()> {
+    if old.query != query || old.filename != filename {
+        return Err(YggError::Config(format!("can't compare runs of {:?} in {} and {query:?} in {filename}", old.query, old.filename)));
+    }
+    Ok(())
+}
+
+/// Prints the changes between the results in two files (`ygg diff`), with a count of each kind.
+pub(crate) fn compare_files(old: &Path, new: &Path) -> Result<()> {
+    let (old, new) = (load(old)?, load(new)?);
+    check_comparable(&old, &new.query, &new.filename)?;
+    let changes = changes(&old, &new);
+    print_changes(&changes);
+
+    let count = |kind: fn(&Change<'_>) -> bool| changes.iter().filter(|change| kind(change)).count();
+    eprintln!(
+        "\n{} repositories changed: {} appeared, {} upgraded, {} downgraded, {} changed, {} disappeared",
+        changes.len(),
+        count(|change| matches!(change, Change::Appeared { .. })),
+        count(|change| matches!(change, Change::Upgraded { .. })),
+        count(|change| matches!(change, Change::Downgraded { .. })),
+        count(|change| matches!(change, Change::Changed { .. })),
+        count(|change| matches!(change, Change::Disappeared { .. }))
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn state(repos: serde_json::Value) -> RunState {
+        serde_json::from_value(json!({ "query": "lodash", "filename": "package-lock.json", "repos": repos })).unwrap()
+    }
+
+    #[test]
+    fn classifies_changes() {
+        let old = state(json!({
+            "acme/api": { "status": "completed", "value": "4.17.20" },
+            "acme/cli": { "status": "completed", "value": "4.17.21" },
+            "acme/gone": { "status": "completed", "value": "3.10.1" },
+            "acme/same": { "status": "completed", "value": "4.17.21" },
+            "acme/tag": { "status": "completed", "value": "latest" },
+            "acme/web": { "status": "not_found" },
+        }));
+        let new = state(json!({
+            "acme/api": { "status": "completed", "value": "4.17.21" },
+            "acme/cli": { "status": "completed", "value": "4.17.20" },
+            "acme/gone": { "status": "completed", "value": "-------" },
+            "acme/same": { "status": "completed", "value": "4.17.21" },
+            "acme/tag": { "status": "completed", "value": "next" },
+            "acme/web": { "status": "completed", "value": "4.17.21" },
+        }));
+        assert_eq!(
+            changes(&old, &new),
+            [
+                Change::Upgraded { repo: "acme/api", from: "4.17.20", to: "4.17.21" },
+                Change::Downgraded { repo: "acme/cli", from: "4.17.21", to: "4.17.20" },
+                Change::Disappeared { repo: "acme/gone", value: "3.10.1" },
+                Change::Changed { repo: "acme/tag", from: "latest", to: "next" },
+                Change::Appeared { repo: "acme/web", value: "4.17.21" },
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_unsettled_repositories() {
+        let old = state(json!({
+            "acme/api": { "status": "failed", "error": "timed out" },
+            "acme/web": { "status": "completed", "value": "4.17.21" },
+            "acme/old": { "status": "completed", "value": "4.17.21" },
+        }));
+        let new = state(json!({
+            "acme/api": { "status": "completed", "value": "4.17.21" },
+            "acme/web": { "status": "blocked", "reason": "SAML SSO" },
+            "acme/new": { "status": "completed", "value": "4.17.21" },
+        }));
+        assert_eq!(changes(&old, &new), []);
+    }
+
+    #[test]
+    fn rejects_runs_of_other_queries() {
+        let old = state(json!({}));
+        assert!(check_comparable(&old, "lodash", "package-lock.json").is_ok());
+        assert!(check_comparable(&old, "react", "package-lock.json").is_err());
+        assert!(check_comparable(&old, "lodash", "yarn.lock").is_err());
+    }
+}
@@ -0,0 +1,405 @@
+//! Repository discovery through the repository listing and repository search endpoints.
+//!
+//! Code search only finds repositories whose files match a query. Listing `/orgs/{org}/repos` finds every
+//! repository an organization (or `/users/{login}/repos` a user) owns, so an audit can cover repositories that
+//! don't match any query yet, and repository search selects them by metadata such as topics. Listings also
+//! expand name patterns like `acme/payments-*` in repository lists.
+//!
+//! Metadata filters (--visibility, --skip-archived, --no-forks, --language) apply to every repository set. Listings and repository search return the
+//! metadata with each repository; for code search results and repository lists it is looked up per repository.
+
+use crate::cache::CacheManager;
+use crate::repolist::RepoEntry;
+use crate::{api_url, glob_match, progress_bar, search_url, GitHubClient, Result, SearchThrottle, YggError};
+use clap::ValueEnum;
+use futures::prelude::*;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Repositories per page, the most the listing endpoints return.
+const PER_PAGE: usize = 100;
+
+/// Search results are capped at 1000, i.e. 10 pages.
+const MAX_SEARCH_PAGES: u64 = 10;
+
+/// Repository metadata returned by the listing and search endpoints.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct RepoMeta {
+    pub(crate) full_name: String,
+    #[serde(default)]
+    pub(crate) archived: bool,
+    #[serde(default)]
+    pub(crate) fork: bool,
+    #[serde(default)]
+    pub(crate) private: bool,
+    pub(crate) visibility: Option<String>,
+    pub(crate) language: Option<String>,
+}
+
+impl RepoMeta {
+    /// Returns true if the repository name matches any of `patterns`, or there are none.
+    pub(crate) fn matches(&self, patterns: &[String]) -> bool {
+        patterns.is_empty() || patterns.iter().any(|pattern| glob_match(pattern, &self.full_name))
+    }
+
+    /// Returns the repository's visibility. Older responses only say whether it is private.
+    pub(crate) fn visibility(&self) -> Visibility {
+        match self.visibility.as_deref() {
+            Some("internal") => Visibility::Internal,
+            Some("private") => Visibility::Private,
+            Some(_) => Visibility::Public,
+            None if self.private => Visibility::Private,
+            None => Visibility::Public,
+        }
+    }
+}
+
+/// Who can see a repository.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Visibility {
+    Public,
+    Private,
+    /// Visible to members of the enterprise (GitHub Enterprise Cloud and Server).
+    Internal,
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Public => "public",
+            Self::Private => "private",
+            Self::Internal => "internal",
+        })
+    }
+}
+
+/// Which repositories to keep, judged by their metadata.
+#[derive(Default, Debug)]
+pub(crate) struct RepoFilter {
+    /// Visibilities to keep; empty keeps all.
+    pub(crate) visibility: Vec<Visibility>,
+    /// Skip archived repositories, including ones from repository lists.
+    pub(crate) skip_archived: bool,
+    /// Keep archived repositories found by discovery, which are skipped by default.
+    pub(crate) keep_archived: bool,
+    /// Skip forks found by discovery. Forks named in a repository list are kept.
+    pub(crate) skip_forks: bool,
+    /// Primary languages to keep, compared case-insensitively; empty keeps all.
+    pub(crate) languages: Vec<String>,
+}
+
+impl RepoFilter {
+    /// Returns true if the filter can drop a repository, i.e. its metadata is needed. `discovered` tells whether
+    /// it was found by discovery rather than read from a repository list.
+    pub(crate) fn is_active(&self, discovered: bool) -> bool {
+        !self.visibility.is_empty()
+            || !self.languages.is_empty()
+            || self.skips_archived(discovered)
+            || (discovered && self.skip_forks)
+    }
+
+    pub(crate) fn keeps(&self, repo: &RepoMeta, discovered: bool) -> bool {
+        (self.visibility.is_empty() || self.visibility.contains(&repo.visibility()))
+            && !(repo.archived && self.skips_archived(discovered))
+            && !(repo.fork && discovered && self.skip_forks)
+            && (self.languages.is_empty()
+                || repo.language.as_deref().is_some_and(|language| {
+                    self.languages.iter().any(|wanted| wanted.eq_ignore_ascii_case(language))
+                }))
+    }
+
+    fn skips_archived(&self, discovered: bool) -> bool {
+        self.skip_archived || (discovered && !self.keep_archived)
+    }
+}
+
+#[derive(Deserialize)]
+struct RepoSearchResponse {
+    total_count: u64,
+    items: Vec<RepoMeta>,
+}
+
+/// Returns a repository search query selecting repositories tagged with every one of `topics`.
+pub(crate) fn topic_query(topics: &[String]) -> String {
+    topics.iter().map(|topic| format!("topic:{topic}")).collect::<Vec<_>>().join(" ")
+}
+
+/// Returns the first page URL of a repository search. An owner qualifier ("org:" or "user:") in the query takes
+/// precedence over `org`.
+pub(crate) fn repo_search_url(query: &str, org: &str) -> String {
+    let has_owner = query.split_whitespace().any(|term| term.starts_with("org:") || term.starts_with("user:"));
+    search_url(&format!("{}/search/repositories", api_url()), query, if has_owner { "" } else { org })
+}
+
+/// Returns the first page URL listing the repositories a user owns, including their forks.
+pub(crate) fn user_repos_url(login: &str) -> String {
+    format!("{}/users/{login}/repos?type=owner&per_page={PER_PAGE}", api_url())
+}
+
+/// Returns the URL of a repository's metadata.
+pub(crate) fn repo_url(full_name: &str) -> String {
+    format!("{}/repos/{full_name}", api_url())
+}
+
+/// Returns the first page URL listing the repositories in an organization.
+pub(crate) fn org_repos_url(org: &str) -> String {
+    format!("{}/orgs/{org}/repos?type=all&per_page={PER_PAGE}", api_url())
+}
+
+/// Lists every repository in `org`, sorted by name.
+pub(crate) async fn list_org_repos(gh_client: &GitHubClient, cache_manager: &CacheManager, org: &str) -> Result<Vec<RepoMeta>> {
+    list_repos(gh_client, cache_manager, &org_repos_url(org)).await.map_err(|e| match e {
+        YggError::NotFound => YggError::ApiError(format!("Organization not found: {org}")),
+        e => e,
+    })
+}
+
+/// Lists every repository `login` owns, sorted by name. Private repositories are only included for the
+/// token's own user.
+pub(crate) async fn list_user_repos(gh_client: &GitHubClient, cache_manager: &CacheManager, login: &str) -> Result<Vec<RepoMeta>> {
+    list_repos(gh_client, cache_manager, &user_repos_url(login)).await.map_err(|e| match e {
+        YggError::NotFound => YggError::ApiError(format!("User not found: {login}")),
+        e => e,
+    })
+}
+
+/// Replaces repository list entries whose name is a pattern (e.g., "acme/payments-*") with an entry for each
+/// matching repository the owner has, keeping the pattern entry's overrides. Repositories also listed explicitly
+/// keep their own entry. The metadata of matching repositories is added to `known`.
+pub(crate) async fn expand_patterns(
+    gh_client: &GitHubClient,
+    cache_manager: &CacheManager,
+    entries: Vec<RepoEntry>,
+    known: &mut HashMap<String, RepoMeta>,
+) -> Result<Vec<RepoEntry>> {
+    let mut seen: HashSet<String> =
+        entries.iter().filter(|entry| !entry.is_pattern()).map(|entry| entry.name().to_string()).collect();
+    let mut listings: HashMap<String, Vec<RepoMeta>> = HashMap::new();
+    let mut expanded = Vec::new();
+
+    for entry in entries {
+        if !entry.is_pattern() {
+            expanded.push(entry);
+            continue;
+        }
+        let owner = entry.name().split('/').next().unwrap_or_default();
+        if owner.contains(['*', '?']) {
+            return Err(YggError::Config(format!("repository patterns must name the owner exactly, got {:?}", entry.name())));
+        }
+        if !listings.contains_key(owner) {
+            // The owner may be an organization or a user
+            let repos = match list_repos(gh_client, cache_manager, &org_repos_url(owner)).await {
+                Err(YggError::NotFound) => list_user_repos(gh_client, cache_manager, owner).await?,
+                result => result?,
+            };
+            listings.insert(owner.to_string(), repos);
+        }
+
+        let matching: Vec<&RepoMeta> = listings[owner].iter().filter(|repo| glob_match(entry.name(), &repo.full_name)).collect();
+        if matching.is_empty() {
+            eprintln!("Warning: no repositories match {}", entry.name());
+        }
+        for repo in matching {
+            if seen.insert(repo.full_name.clone()) {
+                expanded.push(entry.with_name(&repo.full_name));
+            }
+            known.insert(repo.full_name.clone(), repo.clone());
+        }
+    }
+    Ok(expanded)
+}
+
+/// Follows a repository listing page by page until a short page marks the end.
+async fn list_repos(gh_client: &GitHubClient, cache_manager: &CacheManager, url: &str) -> Result<Vec<RepoMeta>> {
+    let pb = progress_bar(0, "{msg} {pos} pages");
+    pb.set_message("Listing repositories");
+
+    // Pages are cached with their ETags like search pages, so unchanged listings cost a 304 each
+    let mut repos = Vec::new();
+    let mut page = 1;
+    loop {
+        let fetched = cache_manager
+            .get_or_fetch_as(&format!("{url}&page={page}"), "application/vnd.github+json", gh_client, |_| {})
+            .await
+            .map_err(|e| match e {
+                YggError::UnexpectedStatus(status) => YggError::ApiError(format!("API error: {status}")),
+                e => e,
+            })?;
+        let items: Vec<RepoMeta> = serde_json::from_slice(&fetched.body)?;
+        let last_page = items.len() < PER_PAGE;
+        repos.extend(items);
+
+        pb.inc(1);
+        if last_page {
+            break;
+        }
+        page += 1;
+    }
+
+    pb.finish_and_clear();
+    repos.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+    Ok(repos)
+}
+
+/// Finds repositories matching a repository search query, scoped to `org` when one is given, sorted by name.
+pub(crate) async fn search_repositories(
+    gh_client: &GitHubClient,
+    cache_manager: &CacheManager,
+    query: &str,
+    org: &str,
+) -> Result<Vec<RepoMeta>> {
+    let first_page_url = repo_search_url(query, org);
+    let pb = progress_bar(1, "{msg} [{bar:40.cyan/blue}] {pos}/{len} (search pages)");
+    pb.set_message("Fetching repository search pages");
+
+    let mut throttle = SearchThrottle::default();
+    let mut repos = Vec::new();
+    let mut page = 1;
+    loop {
+        let url = if page == 1 { first_page_url.clone() } else { format!("{first_page_url}&page={page}") };
+        throttle.wait(&pb).await;
+        let fetched = cache_manager
+            .get_or_fetch_as(&url, "application/vnd.github+json", gh_client, |headers| throttle.update(headers))
+            .await
+            .map_err(|e| match e {
+                YggError::UnexpectedStatus(status) => YggError::ApiError(format!("API error: {status}")),
+                e => e,
+            })?;
+        let response: RepoSearchResponse = serde_json::from_slice(&fetched.body)?;
+
+        let pages = ((response.total_count + PER_PAGE as u64 - 1) / PER_PAGE as u64).clamp(1, MAX_SEARCH_PAGES);
+        pb.set_length(pages);
+        let last_page = response.items.is_empty() || page >= pages;
+        repos.extend(response.items);
+
+        pb.inc(1);
+        if last_page {
+            break;
+        }
+        page += 1;
+    }
+
+    pb.finish_with_message("Repository search complete");
+    repos.sort_by(|a, b| a.full_name.cmp(&b.full_name));
+    repos.dedup_by(|a, b| a.full_name == b.full_name);
+    Ok(repos)
+}
+
+/// Drops the entries `filter` rejects, looking up the metadata of repositories not already in `known`, at most
+/// `parallel` at a time. `discovered` names the repositories found by discovery. Repositories whose metadata can't
+/// be read (not found, or not cached with --offline) are kept, so the run reports them as usual.
+pub(crate) async fn apply_filter(
+    gh_client: &GitHubClient,
+    cache_manager: &CacheManager,
+    filter: &RepoFilter,
+    entries: Vec<RepoEntry>,
+    discovered: &HashSet<String>,
+    known: &mut HashMap<String, RepoMeta>,
+    parallel: usize,
+) -> Result<Vec<RepoEntry>> {
+    let filtered = |entry: &RepoEntry| filter.is_active(discovered.contains(entry.name()));
+    let missing: Vec<&str> =
+        entries.iter().filter(|entry| filtered(entry)).map(RepoEntry::name).filter(|repo| !known.contains_key(*repo)).collect();
+    if !missing.is_empty() {
+        let pb = progress_bar(missing.len() as u64, "{msg} [{bar:40.cyan/blue}] {pos}/{len}");
+        pb.set_message("Looking up repository metadata");
+        let mut lookups = stream::iter(missing)
+            .map(|repo| async move {
+                let fetched = cache_manager.get_or_fetch_as(&repo_url(repo), "application/vnd.github+json", gh_client, |_| {}).await;
+                (repo, fetched.and_then(|fetched| Ok(serde_json::from_slice::<RepoMeta>(&fetched.body)?)))
+            })
+            .buffer_unordered(parallel);
+        let mut unreadable = 0;
+        while let Some((repo, meta)) = lookups.next().await {
+            pb.inc(1);
+            match meta {
+                Ok(meta) => {
+                    known.insert(repo.to_string(), meta);
+                }
+                Err(YggError::NotFound | YggError::NotCached) => unreadable += 1,
+                Err(e) => return Err(e),
+            }
+        }
+        pb.finish_and_clear();
+        if unreadable > 0 {
+            eprintln!("Warning: no metadata for {unreadable} repositories; they are kept regardless of filters");
+        }
+    }
+
+    let before = entries.len();
+    let kept: Vec<RepoEntry> = entries
+        .into_iter()
+        .filter(|entry| known.get(entry.name()).map_or(true, |meta| filter.keeps(meta, discovered.contains(entry.name()))))
+        .collect();
+    if kept.len() < before {
+        eprintln!("Skipped {} repositories filtered out by their metadata", before - kept.len());
+    }
+    Ok(kept)
+}
+
+#[derive(Deserialize)]
+struct Branch {
+    name: String,
+}
+
+/// Returns the first page URL listing a repository's branches.
+pub(crate) fn branches_url(repo: &str) -> String {
+    format!("{}/repos/{repo}/branches?per_page={PER_PAGE}", api_url())
+}
+
+/// Replaces each entry with one entry per branch matching `pattern` (e.g., "release/*"), reading the file on that
+/// branch, listing at most `parallel` repositories at a time. Repositories with no matching branch are dropped.
+pub(crate) async fn expand_branches(
+    gh_client: &GitHubClient,
+    cache_manager: &CacheManager,
+    entries: Vec<RepoEntry>,
+    pattern: &str,
+    parallel: usize,
+) -> Result<Vec<RepoEntry>> {
+    let pb = progress_bar(entries.len() as u64, "{msg} [{bar:40.cyan/blue}] {pos}/{len} (repos)");
+    pb.set_message("Listing branches");
+    let pb = &pb;
+    let listings: Vec<Result<Vec<RepoEntry>>> = stream::iter(entries)
+        .map(|entry| async move {
+            let url = branches_url(entry.name());
+            let mut branches = Vec::new();
+            let mut page = 1;
+            loop {
+                let fetched = match cache_manager
+                    .get_or_fetch_as(&format!("{url}&page={page}"), "application/vnd.github+json", gh_client, |_| {})
+                    .await
+                {
+                    Err(YggError::NotFound) => break,
+                    fetched => fetched?,
+                };
+                let items: Vec<Branch> = serde_json::from_slice(&fetched.body)?;
+                let last_page = items.len() < PER_PAGE;
+                branches.extend(items.into_iter().map(|branch| branch.name).filter(|name| glob_match(pattern, name)));
+                if last_page {
+                    break;
+                }
+                page += 1;
+            }
+            pb.inc(1);
+            branches.sort();
+            Ok(branches.iter().map(|branch| entry.with_ref(branch)).collect())
+        })
+        .buffer_unordered(parallel)
+        .collect()
+        .await;
+    pb.finish_and_clear();
+
+    let mut expanded = Vec::new();
+    let mut without_match = 0;
+    for listing in listings {
+        let listing = listing?;
+        without_match += usize::from(listing.is_empty());
+        expanded.extend(listing);
+    }
+    if without_match > 0 {
+        eprintln!("{without_match} repositories have no branch matching {pattern}");
+    }
+    expanded.sort_by(|a, b| (a.name(), a.git_ref()).cmp(&(b.name(), b.git_ref())));
+    Ok(expanded)
+}
@@ -0,0 +1,97 @@
+//! Findings of the last package audit, shared by the commands that act on them (`ygg report`, `ygg fix`).
+//!
+//! A repository is affected if the version of the package it uses has advisories in the GitHub Advisory
+//! Database, or is below the fix version given on the command line.
+//!
+//! Both commands work through the findings one repository at a time rather than concurrently: GitHub throttles
+//! bursts of content creation (issues, comments, branches, pull requests) with secondary rate limits.
+
+use semver::Version;
+use std::collections::BTreeMap;
+
+use crate::{fetch_advisories, Advisory, GitHubClient, Result, RunState, YggError, RUN_STATE_FILE};
+
+/// An affected repository: the version of the package it uses and what fixes it.
+#[derive(Clone)]
+pub(crate) struct Finding {
+    pub(crate) repo: String,
+    pub(crate) version: String,
+    pub(crate) fix_version: Option<String>,
+    pub(crate) advisories: Vec<Advisory>,
+}
+
+/// The affected repositories of the last package audit.
+pub(crate) struct Findings {
+    pub(crate) package: String,
+    pub(crate) filename: String,
+    pub(crate) findings: Vec<Finding>,
+}
+
+/// Returns the lowest version that fixes every advisory affecting a version: the highest of their first patched
+/// versions for `package`.
+pub(crate) fn patched_version(package: &str, advisories: &[Advisory]) -> Option<String> {
+    advisories
+        .iter()
+        .flat_map(|advisory| &advisory.vulnerabilities)
+        .filter(|vulnerability| vulnerability.package.as_ref().is_some_and(|p| p.name == package))
+        .filter_map(|vulnerability| vulnerability.first_patched_version.as_deref())
+        .filter_map(|version| Version::parse(version).ok())
+        .max()
+        .map(|version| version.to_string())
+}
+
+/// What filing a finding did to the issue or ticket tracking it, identified by its URL or key.
+pub(crate) enum Filed {
+    Created(String),
+    Updated(String),
+    Unchanged(String),
+}
+
+/// Renders advisories as a markdown list, one "- [GHSA-id](url) (severity): summary" line each.
+pub(crate) fn advisory_list(advisories: &[Advisory]) -> String {
+    advisories
+        .iter()
+        .map(|advisory| format!("- [{}]({}) ({}): {}\n", advisory.ghsa_id, advisory.html_url, advisory.severity, advisory.summary))
+        .collect()
+}
+
+/// Loads the last run from ".ygg-run.json" and looks up its findings.
+pub(crate) async fn load(gh_client: &GitHubClient, fix_version: Option<&Version>) -> Result<Findings> {
+    let state = RunState::load(RUN_STATE_FILE)
+        .map_err(|e| YggError::Config(format!("can't read {RUN_STATE_FILE}; run a package audit first ({e})")))?;
+    if state.filename != "package-lock.json" {
+        return Err(YggError::Config("findings come from package audits (--package); the last run was a string search".to_string()));
+    }
+    // Issues, fixes and checks target a repository, not one of several branches of it
+    if state.repos.keys().any(|repo| repo.contains('@')) {
+        return Err(YggError::Config("findings come from audits of one ref per repository; the last run used --branches".to_string()));
+    }
+    from_state(gh_client, &state, fix_version).await
+}
+
+/// Looks up the advisories of each version a package audit found, once per version. `fix_version` overrides the
+/// advisories' first patched version.
+pub(crate) async fn from_state(gh_client: &GitHubClient, state: &RunState, fix_version: Option<&Version>) -> Result<Findings> {
+    let package = state.query.clone();
+
+    let versions: BTreeMap<&str, Vec<&str>> = state.found().fold(BTreeMap::new(), |mut versions, (repo, version)| {
+        versions.entry(version).or_default().push(repo);
+        versions
+    });
+    let mut findings = Vec::new();
+    for (&version, repos) in &versions {
+        let advisories = fetch_advisories(gh_client, &package, version).await?;
+        let below_fix = fix_version.is_some_and(|fix| Version::parse(version).is_ok_and(|v| v < *fix));
+        if advisories.is_empty() && !below_fix {
+            continue;
+        }
+        let fix = fix_version.map(Version::to_string).or_else(|| patched_version(&package, &advisories));
+        findings.extend(repos.iter().map(|&repo| Finding {
+            repo: repo.to_string(),
+            version: version.to_string(),
+            fix_version: fix.clone(),
+            advisories: advisories.clone(),
+        }));
+    }
+    Ok(Findings { package, filename: state.filename.clone(), findings })
+}
@@ -0,0 +1,299 @@
+//! Remediation pull requests (`ygg fix`).
+//!
+//! For each repository the last package audit found affected, regenerates the lockfile with the fix version by
+//! running npm in a scratch directory, commits the changed package.json and package-lock.json to a new branch
+//! through the Git Data API, and opens a pull request. Direct dependencies are bumped with `npm install`;
+//! transitive ones get an entry in package.json's "overrides". Nothing is cloned: only the two files are read
+//! and written.
+
+use reqwest::{Method, StatusCode};
+use semver::Version;
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::cache::CacheManager;
+use crate::findings::{self, Finding};
+use crate::repolist::RepoEntry;
+use crate::{api_url, contents_url, discovery, pins, GitHubClient, Result, YggError};
+
+/// Fix branches are named "ygg/fix-{package}-{version}", so rerunning finds the branch and pull request again.
+const BRANCH_PREFIX: &str = "ygg/fix-";
+
+/// npm flags for regenerating only the lockfile: no node_modules, no install scripts, no audit or funding calls.
+const LOCKFILE_ONLY: [&str; 4] = ["--package-lock-only", "--ignore-scripts", "--no-audit", "--no-fund"];
+
+/// Pull request description, with {package}, {from}, {to}, {advisories} and {repo} filled in.
+const DEFAULT_TEMPLATE: &str = "Bumps `{package}` from `{from}` to `{to}`.
+
+{advisories}
+
+Opened by `ygg fix` from a dependency audit. The lockfile was regenerated with `npm install --package-lock-only`; \
+review the diff and run the tests before merging.
+";
+
+/// How `ygg fix` opens pull requests.
+pub(crate) struct FixOptions {
+    /// Pull request description template; `DEFAULT_TEMPLATE` if unset.
+    pub(crate) template: Option<String>,
+    pub(crate) draft: bool,
+    pub(crate) dry_run: bool,
+}
+
+#[derive(Deserialize)]
+struct RepositoryInfo {
+    default_branch: String,
+}
+
+#[derive(Deserialize)]
+struct PullRequest {
+    html_url: String,
+}
+
+#[derive(Deserialize)]
+struct GitObject {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct GitCommit {
+    tree: GitObject,
+}
+
+/// What happened in a repository.
+enum Outcome {
+    Opened(String),
+    AlreadyOpen(String),
+    Planned(String),
+    Unchanged,
+}
+
+/// Returns the fix branch for bumping `package` to `version`.
+fn branch_name(package: &str, version: &str) -> String {
+    format!("{BRANCH_PREFIX}{}-{version}", package.trim_start_matches('@').replace('/', "-"))
+}
+
+fn render_template(template: &str, repo: &str, package: &str, finding: &Finding, fix: &str) -> String {
+    template
+        .replace("{package}", package)
+        .replace("{from}", &finding.version)
+        .replace("{to}", fix)
+        .replace("{repo}", repo)
+        .replace("{advisories}", findings::advisory_list(&finding.advisories).trim_end())
+}
+
+/// Sends a request whose JSON response is wanted, treating any other status as an error.
+async fn request_json<T: serde::de::DeserializeOwned>(gh_client: &GitHubClient, request: reqwest::RequestBuilder) -> Result<T> {
+    let resp = gh_client.send(request).await?;
+    if !resp.status().is_success() {
+        return Err(YggError::UnexpectedStatus(resp.status()));
+    }
+    Ok(resp.json().await?)
+}
+
+/// Runs npm with `args` in `dir`.
+async fn npm(dir: &Path, args: &[&str]) -> Result<()> {
+    let output = tokio::process::Command::new("npm").args(args).current_dir(dir).output().await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => YggError::Config("ygg fix regenerates lockfiles with npm, which is not on the PATH".to_string()),
+        _ => YggError::Io(e),
+    })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(YggError::Npm(format!("npm {}: {}", args.join(" "), stderr.lines().last().unwrap_or_default())));
+    }
+    Ok(())
+}
+
+/// Returns `manifest` and `lockfile` with `package` at `fix`, regenerated by npm in a scratch directory.
+async fn update_lockfile(repo: &str, package: &str, fix: &str, manifest: &[u8], lockfile: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let dir = env::temp_dir().join(format!("ygg-fix-{}-{}", std::process::id(), repo.replace('/', "-")));
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("package.json"), manifest)?;
+    fs::write(dir.join("package-lock.json"), lockfile)?;
+
+    let parsed: serde_json::Value = serde_json::from_slice(manifest)?;
+    let direct = ["dependencies", "devDependencies", "optionalDependencies"]
+        .into_iter()
+        .find_map(|section| Some((section, parsed.get(section)?.get(package)?.as_str()?)));
+    let result = match direct {
+        Some((section, spec)) => {
+            let save = match section {
+                "devDependencies" => "--save-dev",
+                "optionalDependencies" => "--save-optional",
+                _ => "--save-prod",
+            };
+            let target = format!("{package}@{fix}");
+            let mut args = vec!["install", save, &target];
+            // Keep pinned versions pinned
+            if spec.starts_with(|c: char| c.is_ascii_digit()) {
+                args.push("--save-exact");
+            }
+            args.extend(LOCKFILE_ONLY);
+            npm(&dir, &args).await
+        }
+        None => {
+            // `npm pkg set` edits package.json in place, keeping its key order and formatting
+            let override_arg = format!("overrides[{package}]={fix}");
+            match npm(&dir, &["pkg", "set", &override_arg]).await {
+                Ok(()) => npm(&dir, &[&["install"][..], &LOCKFILE_ONLY[..]].concat()).await,
+                Err(e) => Err(e),
+            }
+        }
+    };
+
+    let updated = result.and_then(|()| Ok((fs::read(dir.join("package.json"))?, fs::read(dir.join("package-lock.json"))?)));
+    let _ = fs::remove_dir_all(&dir);
+    updated
+}
+
+/// Commits `files` on top of `base_sha` and points `branch` at the commit, creating the branch or resetting one
+/// left over from an earlier run whose pull request was closed.
+async fn commit_files(gh_client: &GitHubClient, repo: &str, base_sha: &str, branch: &str, message: &str, files: &[(String, Vec<u8>)]) -> Result<()> {
+    let git_url = format!("{}/repos/{repo}/git", api_url());
+    let base: GitCommit = request_json(gh_client, gh_client.api_request(Method::GET, &format!("{git_url}/commits/{base_sha}"))).await?;
+    let entries: Vec<serde_json::Value> = files
+        .iter()
+        .map(|(path, content)| json!({ "path": path, "mode": "100644", "type": "blob", "content": String::from_utf8_lossy(content) }))
+        .collect();
+    let tree: GitObject = request_json(
+        gh_client,
+        gh_client.api_request(Method::POST, &format!("{git_url}/trees")).json(&json!({ "base_tree": base.tree.sha, "tree": entries })),
+    )
+    .await?;
+    let commit: GitObject = request_json(
+        gh_client,
+        gh_client
+            .api_request(Method::POST, &format!("{git_url}/commits"))
+            .json(&json!({ "message": message, "tree": tree.sha, "parents": [base_sha] })),
+    )
+    .await?;
+
+    let create = gh_client
+        .api_request(Method::POST, &format!("{git_url}/refs"))
+        .json(&json!({ "ref": format!("refs/heads/{branch}"), "sha": commit.sha }));
+    let resp = gh_client.send(create).await?;
+    match resp.status() {
+        status if status.is_success() => Ok(()),
+        // The branch already exists
+        StatusCode::UNPROCESSABLE_ENTITY => {
+            let update = gh_client
+                .api_request(Method::PATCH, &format!("{git_url}/refs/heads/{branch}"))
+                .json(&json!({ "sha": commit.sha, "force": true }));
+            request_json::<serde_json::Value>(gh_client, update).await.map(|_| ())
+        }
+        status => Err(YggError::UnexpectedStatus(status)),
+    }
+}
+
+/// Opens the fix pull request for one repository, reading the files at `entry`'s directory and branch if it has
+/// them, or the root of the default branch.
+async fn fix_repo(
+    gh_client: &GitHubClient,
+    cache_manager: &CacheManager,
+    package: &str,
+    finding: &Finding,
+    fix: &str,
+    entry: Option<&RepoEntry>,
+    options: &FixOptions,
+) -> Result<Outcome> {
+    let repo = finding.repo.as_str();
+    let base_branch = match entry.and_then(RepoEntry::git_ref) {
+        Some(git_ref) => git_ref.to_string(),
+        None => {
+            let info: RepositoryInfo = request_json(gh_client, gh_client.api_request(Method::GET, &discovery::repo_url(repo))).await?;
+            info.default_branch
+        }
+    };
+    let branch = branch_name(package, fix);
+
+    let owner = repo.split('/').next().unwrap_or_default();
+    let head = url::form_urlencoded::byte_serialize(format!("{owner}:{branch}").as_bytes()).collect::<String>();
+    let open: Vec<PullRequest> =
+        request_json(gh_client, gh_client.api_request(Method::GET, &format!("{}/repos/{repo}/pulls?state=open&head={head}", api_url())))
+            .await?;
+    if let Some(pull) = open.into_iter().next() {
+        return Ok(Outcome::AlreadyOpen(pull.html_url));
+    }
+    if options.dry_run {
+        return Ok(Outcome::Planned(format!("{branch} -> {base_branch}")));
+    }
+
+    let base_sha = pins::resolve_sha(gh_client, repo, Some(&base_branch)).await?;
+    let paths = entry.map_or_else(
+        || ("package.json".to_string(), "package-lock.json".to_string()),
+        |entry| (entry.file_path("package.json"), entry.file_path("package-lock.json")),
+    );
+    let manifest = gh_client.fetch_raw_file(&contents_url(repo, &paths.0, Some(&base_sha)), cache_manager).await?.body;
+    let lockfile = gh_client.fetch_raw_file(&contents_url(repo, &paths.1, Some(&base_sha)), cache_manager).await?.body;
+    let (new_manifest, new_lockfile) = update_lockfile(repo, package, fix, &manifest, &lockfile).await?;
+    if new_manifest == manifest && new_lockfile == lockfile {
+        return Ok(Outcome::Unchanged);
+    }
+
+    let title = format!("Bump {package} from {} to {fix}", finding.version);
+    let files = [(paths.0, new_manifest), (paths.1, new_lockfile)];
+    commit_files(gh_client, repo, &base_sha, &branch, &title, &files).await?;
+
+    let body = render_template(options.template.as_deref().unwrap_or(DEFAULT_TEMPLATE), repo, package, finding, fix);
+    let pull: PullRequest = request_json(
+        gh_client,
+        gh_client.api_request(Method::POST, &format!("{}/repos/{repo}/pulls", api_url())).json(&json!({
+            "title": title,
+            "head": branch,
+            "base": base_branch,
+            "body": body,
+            "draft": options.draft,
+        })),
+    )
+    .await?;
+    Ok(Outcome::Opened(pull.html_url))
+}
+
+/// Opens a pull request bumping the package to its fix version in every repository the last package audit found
+/// affected. `entries` are the repos file entries, for repositories whose files live in a subdirectory or whose
+/// fixes should target another branch.
+pub(crate) async fn run(
+    gh_client: &GitHubClient,
+    cache_manager: &CacheManager,
+    entries: &[RepoEntry],
+    fix_version: Option<&Version>,
+    options: &FixOptions,
+) -> Result<()> {
+    let findings::Findings { package, findings, .. } = findings::load(gh_client, fix_version).await?;
+    if findings.is_empty() {
+        eprintln!("No affected repositories in the last audit of {package}; nothing to fix.");
+        return Ok(());
+    }
+
+    // One at a time, see the findings module
+    let (mut opened, mut failed) = (0, 0);
+    for finding in &findings {
+        let Some(fix) = finding.fix_version.as_deref() else {
+            eprintln!("{}: no patched version of {package} {} is published; pass --fix-version", finding.repo, finding.version);
+            failed += 1;
+            continue;
+        };
+        let entry = entries.iter().find(|entry| entry.name() == finding.repo);
+        match fix_repo(gh_client, cache_manager, &package, finding, fix, entry, options).await {
+            Ok(Outcome::Opened(url)) => {
+                opened += 1;
+                println!("opened        {url}");
+            }
+            Ok(Outcome::AlreadyOpen(url)) => println!("already open  {url}"),
+            Ok(Outcome::Planned(branches)) => println!("{}: would open {branches} bumping {package} to {fix}", finding.repo),
+            Ok(Outcome::Unchanged) => eprintln!("{}: npm left the lockfile unchanged; nothing to commit", finding.repo),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {e}", finding.repo);
+            }
+        }
+    }
+
+    if !options.dry_run {
+        eprintln!("\n{opened} pull requests opened, {failed} repositories failed");
+    }
+    cache_manager.finish().await;
+    Ok(())
+}
@@ -0,0 +1,160 @@
+//! Batched file fetches through the GitHub GraphQL API.
+//!
+//! A single GraphQL query can read the same file from many repositories at once, turning one REST request per
+//! repository into one request per batch.
+
+use futures::prelude::*;
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use crate::{api_url, FetchedFile, GitHubClient, Result, YggError};
+
+/// Returns the GraphQL endpoint: "/graphql" on api.github.com, "/api/graphql" on GitHub Enterprise Server.
+fn graphql_url() -> String {
+    match api_url().strip_suffix("/v3") {
+        Some(api) => format!("{api}/graphql"),
+        None => format!("{}/graphql", api_url()),
+    }
+}
+
+/// Repositories per query. Larger batches risk GraphQL resource limits and timeouts on big blobs.
+const BATCH_SIZE: usize = 50;
+
+/// Batches fetched concurrently.
+const CONCURRENT_BATCHES: usize = 4;
+
+/// Result of reading a file from one repository via GraphQL.
+pub(crate) enum Blob {
+    Text(String),
+    /// The repository or file does not exist.
+    Missing,
+    /// The blob is binary or too large to be returned inline; it must be fetched over REST instead.
+    Oversized,
+}
+
+/// Fetches a file from each repository, keyed by the repository's index in `repos`. Each repository is given
+/// with the object expression of its file, e.g. "HEAD:package-lock.json".
+///
+/// Batches that fail entirely are omitted from the result so their repositories fall back to REST.
+pub(crate) async fn fetch_blobs(gh_client: &GitHubClient, repos: &[(usize, &str, String)]) -> HashMap<usize, Blob> {
+    stream::iter(repos.chunks(BATCH_SIZE))
+        .map(|batch| {
+            async move {
+                match fetch_batch(gh_client, batch).await {
+                    Ok(blobs) => blobs,
+                    Err(e) => {
+                        eprintln!("GraphQL batch failed, falling back to REST: {e}");
+                        Vec::new()
+                    }
+                }
+            }
+        })
+        .buffer_unordered(CONCURRENT_BATCHES)
+        .flat_map(stream::iter)
+        .collect()
+        .await
+}
+
+async fn fetch_batch(gh_client: &GitHubClient, batch: &[(usize, &str, String)]) -> Result<Vec<(usize, Blob)>> {
+    let mut declarations = Vec::new();
+    let mut fields = Vec::new();
+    let mut variables = Map::new();
+
+    for (n, (_, repo, expression)) in batch.iter().enumerate() {
+        let (owner, name) = repo.split_once('/').unwrap_or((repo, ""));
+        declarations.push(format!("$o{n}: String!, $n{n}: String!, $e{n}: String!"));
+        fields.push(format!(
+            "r{n}: repository(owner: $o{n}, name: $n{n}) {{ object(expression: $e{n}) {{ ... on Blob {{ text isTruncated isBinary }} }} }}"
+        ));
+        variables.insert(format!("o{n}"), json!(owner));
+        variables.insert(format!("n{n}"), json!(name));
+        variables.insert(format!("e{n}"), json!(expression));
+    }
+
+    let query = format!("query({}) {{ {} }}", declarations.join(", "), fields.join(" "));
+    let body = json!({ "query": query, "variables": variables });
+
+    let resp = gh_client.send(gh_client.api_request(Method::POST, &graphql_url()).json(&body)).await?;
+
+    if resp.status() != StatusCode::OK {
+        return Err(YggError::UnexpectedStatus(resp.status()));
+    }
+
+    // Missing repositories are reported in "errors" alongside partial "data", so only "data" is inspected
+    let response: Value = resp.json().await?;
+    let data = response.get("data").and_then(Value::as_object)
+        .ok_or_else(|| YggError::ApiError(format!("GraphQL error: {}", response.get("errors").unwrap_or(&Value::Null))))?;
+
+    Ok(batch.iter().enumerate().map(|(n, (i, _, _))| {
+        let object = data.get(&format!("r{n}")).and_then(|repo| repo.get("object")).filter(|o| !o.is_null());
+        let blob = match object {
+            None => Blob::Missing,
+            Some(object) => {
+                let truncated = object.get("isTruncated").and_then(Value::as_bool).unwrap_or(true);
+                match object.get("text").and_then(Value::as_str) {
+                    Some(text) if !truncated => Blob::Text(text.to_string()),
+                    _ => Blob::Oversized,
+                }
+            }
+        };
+        (*i, blob)
+    }).collect())
+}
+
+impl Blob {
+    /// Converts the blob into a fetch result, or `None` if it has to be fetched over REST.
+    pub(crate) fn into_fetched(self) -> Option<Result<FetchedFile>> {
+        match self {
+            Blob::Text(text) => Some(Ok(FetchedFile { body: text.into_bytes(), moved: false })),
+            Blob::Missing => Some(Err(YggError::NotFound)),
+            Blob::Oversized => None,
+        }
+    }
+}
+
+/// The commit that last changed a line, from `git blame`.
+pub(crate) struct BlameLine {
+    pub(crate) sha: String,
+    pub(crate) date: String,
+    pub(crate) author: String,
+}
+
+/// Blames `line` (1-based) of the file at `path` in `repo`, at the commit `expression` resolves to (e.g., "HEAD").
+/// Returns None if the repository, ref or file doesn't exist.
+pub(crate) async fn blame_line(gh_client: &GitHubClient, repo: &str, expression: &str, path: &str, line: usize) -> Result<Option<BlameLine>> {
+    let (owner, name) = repo.split_once('/').unwrap_or((repo, ""));
+    let query = "query($o: String!, $n: String!, $e: String!, $p: String!) { repository(owner: $o, name: $n) { \
+        object(expression: $e) { ... on Commit { blame(path: $p) { ranges { startingLine endingLine \
+        commit { oid committedDate author { name user { login } } } } } } } } }";
+    let body = json!({ "query": query, "variables": { "o": owner, "n": name, "e": expression, "p": path } });
+
+    let resp = gh_client.send(gh_client.api_request(Method::POST, &graphql_url()).json(&body)).await?;
+
+    if resp.status() != StatusCode::OK {
+        return Err(YggError::UnexpectedStatus(resp.status()));
+    }
+
+    let response: Value = resp.json().await?;
+    let ranges = response.pointer("/data/repository/object/blame/ranges").and_then(Value::as_array);
+    let Some(ranges) = ranges else {
+        // Missing repositories, refs and files come back as null data alongside "errors", like in fetch_batch
+        return match response.get("data").filter(|data| !data.is_null()) {
+            Some(_) => Ok(None),
+            None => Err(YggError::ApiError(format!("GraphQL error: {}", response.get("errors").unwrap_or(&Value::Null)))),
+        };
+    };
+
+    let covers = |range: &&Value| {
+        let bound = |key| range.get(key).and_then(Value::as_u64).unwrap_or(0) as usize;
+        (bound("startingLine")..=bound("endingLine")).contains(&line)
+    };
+    Ok(ranges.iter().find(covers).and_then(|range| range.get("commit")).map(|commit| {
+        let text = |pointer| commit.pointer(pointer).and_then(Value::as_str).map(str::to_string);
+        BlameLine {
+            sha: text("/oid").unwrap_or_default().chars().take(12).collect(),
+            date: text("/committedDate").unwrap_or_else(|| "-".to_string()),
+            author: text("/author/user/login").or_else(|| text("/author/name")).unwrap_or_else(|| "-".to_string()),
+        }
+    }))
+}
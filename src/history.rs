@@ -0,0 +1,206 @@
+//! Commit history mode (--history).
+//!
+//! Walks the commits that touched the file, newest first, through the path-filtered commits API, reading the file
+//! at each one until the value changes. The oldest commit of that unbroken run is when the repository picked up
+//! its current value, e.g. when a compromised release landed in its lockfile.
+//!
+//! --since and --until limit the walk to a window, and no walk reads more than `MAX_COMMITS` commits: lockfiles
+//! change often and each commit's copy is a full download. A walk that stops before the value changes reports the
+//! oldest commit it read, marked "<=" since the value may be older still.
+
+use chrono::{DateTime, NaiveDate};
+use futures::prelude::*;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+use crate::cache::CacheManager;
+use crate::repolist::RepoEntry;
+use crate::{api_url, contents_url, entry_ref, process_file, progress_bar, Cli, GitHubClient, Result, YggError};
+
+/// Commits per page, the most the commits endpoint returns.
+const PER_PAGE: usize = 100;
+
+/// Most commits read per repository.
+const MAX_COMMITS: usize = 500;
+
+/// Parses a --since or --until date, "2024-03-01" or an RFC 3339 timestamp, into the ISO 8601 timestamp the
+/// commits API takes. Dates mean midnight UTC.
+pub(crate) fn parse_date(s: &str) -> std::result::Result<String, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(format!("{date}T00:00:00Z"));
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|time| time.to_utc().format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .map_err(|_| format!("invalid date {s:?}, expected e.g. \"2024-03-01\" or \"2024-03-01T12:00:00Z\""))
+}
+
+#[derive(Deserialize)]
+struct CommitSummary {
+    sha: String,
+    commit: CommitDetail,
+    author: Option<Account>,
+}
+
+#[derive(Deserialize)]
+struct CommitDetail {
+    committer: Option<Signature>,
+    author: Option<Signature>,
+}
+
+#[derive(Deserialize)]
+struct Signature {
+    name: Option<String>,
+    date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    login: String,
+}
+
+/// The commit a repository's file took on its current value.
+struct Introduction {
+    value: String,
+    sha: String,
+    date: String,
+    author: String,
+    /// Whether the walk stopped before the value changed, so the value may be older than this commit.
+    at_or_before: bool,
+}
+
+impl CommitSummary {
+    fn introduction(&self, value: &str) -> Introduction {
+        let signature = self.commit.committer.as_ref().or(self.commit.author.as_ref());
+        Introduction {
+            value: value.to_string(),
+            sha: self.sha.chars().take(12).collect(),
+            date: signature.and_then(|s| s.date.clone()).unwrap_or_else(|| "-".to_string()),
+            author: match (&self.author, self.commit.author.as_ref().and_then(|s| s.name.as_ref())) {
+                (Some(account), _) => account.login.clone(),
+                (None, Some(name)) => name.clone(),
+                (None, None) => "-".to_string(),
+            },
+            at_or_before: false,
+        }
+    }
+}
+
+/// Returns the URL listing the commits that touched `path` at `git_ref`, within --since and --until.
+fn commits_url(cli: &Cli, repo: &str, path: &str, git_ref: Option<&str>) -> String {
+    let encode = |value: &str| url::form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+    let mut url = format!("{}/repos/{repo}/commits?path={}&per_page={PER_PAGE}", api_url(), encode(path));
+    for (name, value) in [("sha", git_ref), ("since", cli.since.as_deref()), ("until", cli.until.as_deref())] {
+        if let Some(value) = value {
+            url.push_str(&format!("&{name}={}", encode(value)));
+        }
+    }
+    url
+}
+
+/// Returns the commit the file at `path` took on its current value, or None if the file doesn't exist or has no
+/// match. `commits_url` lists the commits to walk. Each commit's copy of the file goes through the cache, which
+/// never needs revalidating for a SHA.
+async fn find_introduction(
+    gh_client: &GitHubClient,
+    cache_manager: &CacheManager,
+    repo: &str,
+    path: &str,
+    commits_url: &str,
+    is_package_lock: bool,
+    query: &str,
+) -> Result<Option<Introduction>> {
+    let windowed = commits_url.contains("&since=");
+
+    let mut current: Option<String> = None;
+    let mut introduced: Option<Introduction> = None;
+    let mut page = 1;
+    let mut read = 0;
+    loop {
+        let fetched = cache_manager
+            .get_or_fetch_as(&format!("{commits_url}&page={page}"), "application/vnd.github+json", gh_client, |_| {})
+            .await?;
+        let commits: Vec<CommitSummary> = serde_json::from_slice(&fetched.body)?;
+        let last_page = commits.len() < PER_PAGE;
+
+        for commit in &commits {
+            if read == MAX_COMMITS {
+                return Ok(introduced.map(|introduction| Introduction { at_or_before: true, ..introduction }));
+            }
+            read += 1;
+            let value = match gh_client.fetch_raw_file(&contents_url(repo, path, Some(&commit.sha)), cache_manager).await {
+                Ok(file) => process_file(&file.body, is_package_lock, query),
+                // Deleted by this commit
+                Err(YggError::NotFound) => "-------".to_string(),
+                Err(e) => return Err(e),
+            };
+            match &current {
+                None if value == "-------" => return Ok(None),
+                None => current = Some(value.clone()),
+                Some(current) if *current != value => return Ok(introduced),
+                Some(_) => {}
+            }
+            introduced = Some(commit.introduction(&value));
+        }
+
+        if last_page {
+            // The file has had this value since it was added, or since before the window
+            return Ok(introduced.map(|introduction| Introduction { at_or_before: windowed, ..introduction }));
+        }
+        page += 1;
+    }
+}
+
+/// Finds, for every repository, the commit its file took on its current value, at most `parallel` repositories at
+/// a time, and prints them sorted by date so picked-up releases can be matched against an incident window.
+pub(crate) async fn run(
+    cli: &Cli,
+    gh_client: &GitHubClient,
+    cache_manager: &CacheManager,
+    entries: &[RepoEntry],
+    filename: &str,
+    query: &str,
+    parallel: usize,
+) -> Result<()> {
+    let is_package_lock = cli.filename.is_none();
+    let qualified = entries.iter().map(|entry| entry.name().split('/').next()).collect::<HashSet<_>>().len() > 1;
+
+    let pb = progress_bar(entries.len() as u64, "{msg} [{bar:40.cyan/blue}] {pos}/{len} (repos) ETA {eta}");
+    pb.set_message("Walking file history");
+    let pb = &pb;
+    let mut rows: Vec<(String, Introduction)> = stream::iter(entries)
+        .map(|entry| async move {
+            let path = entry.file_path(filename);
+            let commits_url = commits_url(cli, entry.name(), &path, entry_ref(cli, entry));
+            let found = find_introduction(gh_client, cache_manager, entry.name(), &path, &commits_url, is_package_lock, query).await;
+            pb.inc(1);
+            let name = if qualified { entry.name() } else { entry.name().split('/').nth(1).unwrap_or_default() };
+            match found {
+                Ok(found) => found.map(|introduction| (name.to_string(), introduction)),
+                Err(e) => {
+                    pb.suspend(|| eprintln!("{}: {e}", entry.name()));
+                    None
+                }
+            }
+        })
+        .buffer_unordered(parallel)
+        .filter_map(future::ready)
+        .collect()
+        .await;
+    pb.finish_and_clear();
+    rows.sort_by(|(a_name, a), (b_name, b)| (&a.date, a_name).cmp(&(&b.date, b_name)));
+
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max(4);
+    let value_width = rows.iter().map(|(_, row)| row.value.len()).max().unwrap_or(0).max(5);
+    println!("{:<width$}  {:<value_width$}  {:<20}  {:<12}  author", "repo", "value", "since", "commit");
+    for (name, row) in &rows {
+        let since = if row.at_or_before { format!("<={}", row.date) } else { row.date.clone() };
+        println!("{name:<width$}  {:<value_width$}  {since:<20}  {:<12}  {}", row.value, row.sha, row.author);
+    }
+    eprintln!("\n{} of {} repositories have a match", rows.len(), entries.len());
+    if rows.iter().any(|(_, row)| row.at_or_before) {
+        eprintln!("Rows marked \"<=\" reached the start of --since or {MAX_COMMITS} commits before the value changed; narrow the window to look further back");
+    }
+
+    cache_manager.finish().await;
+    Ok(())
+}
@@ -0,0 +1,155 @@
+//! Issues for findings (`ygg report --create-issues`).
+//!
+//! Files an issue in each repository the last package audit found an affected version in, naming the package,
+//! the version in use, the version that fixes it and the advisories involved. Issues carry a marker label and a
+//! hidden marker in their body, so rerunning after the next audit updates the open issue instead of filing a
+//! second one.
+
+use reqwest::{Method, StatusCode};
+use semver::Version;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::findings::{self, Filed, Finding};
+use crate::{api_url, GitHubClient, Result, YggError};
+
+/// Label new issues are filed with, and existing ones are found by.
+pub(crate) const DEFAULT_LABEL: &str = "ygg";
+
+#[derive(Deserialize)]
+struct Issue {
+    number: u64,
+    html_url: String,
+    title: String,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+/// Returns the hidden marker that identifies the issue for `package`.
+fn marker(package: &str) -> String {
+    format!("<!-- ygg:finding {package} -->")
+}
+
+fn issue_title(package: &str, finding: &Finding) -> String {
+    match &finding.fix_version {
+        Some(fix) => format!("Update {package} from {} to {fix} or later", finding.version),
+        None => format!("{package} {} has known vulnerabilities", finding.version),
+    }
+}
+
+fn issue_body(package: &str, filename: &str, finding: &Finding) -> String {
+    let mut body = format!("{}\n", marker(package));
+    body.push_str(&format!("A dependency audit found **{package}@{}** in `{filename}`.\n\n", finding.version));
+    body.push_str("| | |\n|---|---|\n");
+    body.push_str(&format!("| Package | `{package}` |\n"));
+    body.push_str(&format!("| Current version | `{}` |\n", finding.version));
+    body.push_str(&format!("| Fix version | {} |\n", finding.fix_version.as_deref().map_or("none published".to_string(), |v| format!("`{v}`"))));
+    if !finding.advisories.is_empty() {
+        body.push_str("\n**Advisories**\n\n");
+        body.push_str(&findings::advisory_list(&finding.advisories));
+    }
+    if let Some(fix) = &finding.fix_version {
+        body.push_str(&format!("\nUpdate `{package}` to `{fix}` or later and regenerate the lockfile.\n"));
+    }
+    body.push_str(&format!("\n_This issue is updated by ygg on each audit of {}._\n", finding.repo));
+    body
+}
+
+/// Returns the open issue filed for `package` in `repo`, if any, looking through every open issue with `label`.
+async fn find_issue(gh_client: &GitHubClient, repo: &str, label: &str, package: &str) -> Result<Option<Issue>> {
+    let marker = marker(package);
+    let label = url::form_urlencoded::byte_serialize(label.as_bytes()).collect::<String>();
+    let mut page = 1;
+    loop {
+        let url = format!("{}/repos/{repo}/issues?state=open&per_page=100&labels={label}&page={page}", api_url());
+        let resp = gh_client.send(gh_client.api_request(Method::GET, &url)).await?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => return Err(YggError::NotFound),
+            StatusCode::GONE => return Err(YggError::ApiError("issues are disabled".to_string())),
+            status if !status.is_success() => return Err(YggError::UnexpectedStatus(status)),
+            _ => {}
+        }
+        let issues: Vec<Issue> = resp.json().await?;
+        let last_page = issues.len() < 100;
+        if let Some(issue) = issues.into_iter().find(|issue| issue.body.as_deref().is_some_and(|body| body.contains(&marker))) {
+            return Ok(Some(issue));
+        }
+        if last_page {
+            return Ok(None);
+        }
+        page += 1;
+    }
+}
+
+/// Creates the issue for a finding, or updates the open one if its title or body changed.
+async fn file_issue(gh_client: &GitHubClient, repo: &str, label: &str, package: &str, title: &str, body: &str) -> Result<Filed> {
+    let (method, url, payload) = match find_issue(gh_client, repo, label, package).await? {
+        Some(issue) if issue.title == title && issue.body.as_deref() == Some(body) => return Ok(Filed::Unchanged(issue.html_url)),
+        Some(issue) => (
+            Method::PATCH,
+            format!("{}/repos/{repo}/issues/{}", api_url(), issue.number),
+            json!({ "title": title, "body": body }),
+        ),
+        None => (
+            Method::POST,
+            format!("{}/repos/{repo}/issues", api_url()),
+            json!({ "title": title, "body": body, "labels": [label] }),
+        ),
+    };
+    let created = method == Method::POST;
+    let resp = gh_client.send(gh_client.api_request(method, &url).json(&payload)).await?;
+    if !resp.status().is_success() {
+        return Err(YggError::UnexpectedStatus(resp.status()));
+    }
+    let issue: Issue = resp.json().await?;
+    Ok(if created { Filed::Created(issue.html_url) } else { Filed::Updated(issue.html_url) })
+}
+
+/// Files or updates an issue in every repository the last run found an affected version of its package in.
+/// A version is affected if it has advisories, or is below `fix_version` when one is given. With `dry_run`, prints
+/// what would be filed instead.
+pub(crate) async fn create_issues(gh_client: &GitHubClient, label: &str, fix_version: Option<&Version>, dry_run: bool) -> Result<()> {
+    let findings::Findings { package, filename, findings } = findings::load(gh_client, fix_version).await?;
+    let package = package.as_str();
+    if findings.is_empty() {
+        eprintln!("No affected repositories in the last audit of {package}; no issues to file.");
+        return Ok(());
+    }
+
+    // One at a time, see the findings module
+    let (mut created, mut updated, mut unchanged, mut failed) = (0, 0, 0, 0);
+    for finding in &findings {
+        let title = issue_title(package, finding);
+        let body = issue_body(package, &filename, finding);
+        if dry_run {
+            println!("{}: {title}", finding.repo);
+            continue;
+        }
+        match file_issue(gh_client, &finding.repo, label, package, &title, &body).await {
+            Ok(Filed::Created(url)) => {
+                created += 1;
+                println!("created    {url}");
+            }
+            Ok(Filed::Updated(url)) => {
+                updated += 1;
+                println!("updated    {url}");
+            }
+            Ok(Filed::Unchanged(url)) => {
+                unchanged += 1;
+                println!("unchanged  {url}");
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {e}", finding.repo);
+            }
+        }
+    }
+
+    if dry_run {
+        eprintln!("\nDry run: would file or update {} issues labeled \"{label}\". Example body:\n", findings.len());
+        eprintln!("{}", issue_body(package, &filename, &findings[0]));
+    } else {
+        eprintln!("\n{created} issues created, {updated} updated, {unchanged} unchanged, {failed} failed");
+    }
+    Ok(())
+}
@@ -0,0 +1,338 @@
+//! Jira tickets for findings (`ygg report --jira`).
+//!
+//! Groups the repositories the last package audit found affected by owning team and opens one ticket per team
+//! listing its repositories, versions and advisories. A repository's team is its "team:NAME" tag in the repos
+//! file, or else the GitHub team with the most access to it. Tickets carry a label and a fixed summary, so
+//! rerunning after the next audit updates each team's open ticket instead of opening another.
+
+use reqwest::{Client, Method};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env;
+
+use crate::findings::{self, Filed, Finding};
+use crate::repolist::RepoEntry;
+use crate::{api_url, GitHubClient, NetworkOptions, Result, YggError};
+
+/// Tag prefix naming a repository's owning team in the repos file, e.g. "team:payments".
+const TEAM_TAG: &str = "team:";
+
+/// Team for repositories without a team tag or a GitHub team.
+const UNOWNED: &str = "unowned";
+
+/// The `[jira]` table of .ygg.toml.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub(crate) struct JiraConfig {
+    /// Base URL of the Jira site, e.g. "https://example.atlassian.net".
+    url: String,
+    /// Key of the project tickets are opened in.
+    project: String,
+    /// Account email for Jira Cloud; without one the token is sent as a bearer token (Jira Data Center).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    /// API token, or "$VAR" naming an environment variable holding it. Defaults to JIRA_API_TOKEN.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    api_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    issue_type: Option<String>,
+    /// Label tickets are opened with, which is also how earlier tickets are found.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Team {
+    slug: String,
+    #[serde(default)]
+    permission: String,
+}
+
+#[derive(Deserialize)]
+struct SearchResults {
+    issues: Vec<Ticket>,
+    #[serde(default)]
+    total: usize,
+}
+
+#[derive(Deserialize)]
+struct Ticket {
+    key: String,
+    fields: TicketFields,
+}
+
+#[derive(Deserialize)]
+struct TicketFields {
+    summary: String,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreatedTicket {
+    key: String,
+}
+
+/// A Jira site with its credentials resolved.
+struct Jira<'a> {
+    client: Client,
+    config: &'a JiraConfig,
+    token: String,
+}
+
+impl Jira<'_> {
+    fn label(&self) -> &str {
+        self.config.label.as_deref().unwrap_or(crate::issues::DEFAULT_LABEL)
+    }
+
+    fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        let url = format!("{}/rest/api/2/{path}", self.config.url.trim_end_matches('/'));
+        let request = self.client.request(method, url);
+        match &self.config.email {
+            Some(email) => request.basic_auth(email, Some(&self.token)),
+            None => request.bearer_auth(&self.token),
+        }
+    }
+
+    fn browse_url(&self, key: &str) -> String {
+        format!("{}/browse/{key}", self.config.url.trim_end_matches('/'))
+    }
+
+    /// Returns the open ticket with `summary`, if any.
+    async fn find_ticket(&self, summary: &str) -> Result<Option<Ticket>> {
+        // The summary is searched for as a phrase, itself quoted inside the JQL string
+        let phrase = format!("\"{}\"", summary.replace(['"', '\\'], ""));
+        let jql = format!(
+            "project = {} AND labels = {} AND statusCategory != Done AND summary ~ {}",
+            jql_string(&self.config.project),
+            jql_string(self.label()),
+            jql_string(&phrase)
+        );
+        let mut start_at = 0;
+        loop {
+            let resp = self
+                .request(Method::GET, "search")
+                .query(&[("jql", jql.as_str()), ("fields", "summary,description"), ("maxResults", "50")])
+                .query(&[("startAt", start_at)])
+                .send()
+                .await?;
+            if !resp.status().is_success() {
+                return Err(jira_error(resp).await);
+            }
+            let results: SearchResults = resp.json().await?;
+            start_at += results.issues.len();
+            let last_page = results.issues.is_empty() || start_at >= results.total;
+            // "~" is a fuzzy text match, so compare exactly
+            if let Some(ticket) = results.issues.into_iter().find(|ticket| ticket.fields.summary == summary) {
+                return Ok(Some(ticket));
+            }
+            if last_page {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Opens the ticket for a team, or updates the open one if its description changed.
+    async fn file_ticket(&self, summary: &str, description: &str) -> Result<Filed> {
+        match self.find_ticket(summary).await? {
+            Some(ticket) if ticket.fields.description.as_deref() == Some(description) => Ok(Filed::Unchanged(ticket.key)),
+            Some(ticket) => {
+                let resp = self
+                    .request(Method::PUT, &format!("issue/{}", ticket.key))
+                    .json(&json!({ "fields": { "description": description } }))
+                    .send()
+                    .await?;
+                if !resp.status().is_success() {
+                    return Err(jira_error(resp).await);
+                }
+                Ok(Filed::Updated(ticket.key))
+            }
+            None => {
+                let fields = json!({
+                    "project": { "key": self.config.project },
+                    "summary": summary,
+                    "description": description,
+                    "issuetype": { "name": self.config.issue_type.as_deref().unwrap_or("Task") },
+                    "labels": [self.label()],
+                });
+                let resp = self.request(Method::POST, "issue").json(&json!({ "fields": fields })).send().await?;
+                if !resp.status().is_success() {
+                    return Err(jira_error(resp).await);
+                }
+                let ticket: CreatedTicket = resp.json().await?;
+                Ok(Filed::Created(ticket.key))
+            }
+        }
+    }
+}
+
+/// Quotes `value` as a JQL string, escaping the quotes and backslashes in it.
+fn jql_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Returns an error with Jira's error messages for a failed request.
+async fn jira_error(resp: reqwest::Response) -> YggError {
+    let status = resp.status();
+    let body: serde_json::Value = resp.json().await.unwrap_or_default();
+    let messages: Vec<String> = body["errorMessages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|m| m.as_str().map(str::to_string))
+        .chain(body["errors"].as_object().into_iter().flatten().map(|(field, m)| format!("{field}: {}", m.as_str().unwrap_or_default())))
+        .collect();
+    if messages.is_empty() {
+        YggError::ApiError(format!("Jira responded {status}"))
+    } else {
+        YggError::ApiError(format!("Jira responded {status}: {}", messages.join("; ")))
+    }
+}
+
+/// Returns the API token: `api_token`, read from the environment if it starts with "$", or JIRA_API_TOKEN.
+fn api_token(config: &JiraConfig) -> Result<String> {
+    let token = match config.api_token.as_deref() {
+        Some(value) => match value.strip_prefix('$') {
+            Some(var) => env::var(var).map_err(|_| YggError::Config(format!("jira.api_token names {var}, which is not set")))?,
+            None => value.to_string(),
+        },
+        None => env::var("JIRA_API_TOKEN")
+            .map_err(|_| YggError::Config("set JIRA_API_TOKEN or jira.api_token in .ygg.toml".to_string()))?,
+    };
+    Ok(token.trim().to_string())
+}
+
+/// Ranks repository permissions, so the team with the most access owns the repository.
+fn permission_rank(permission: &str) -> u8 {
+    match permission {
+        "admin" => 4,
+        "maintain" => 3,
+        "push" => 2,
+        "triage" => 1,
+        _ => 0,
+    }
+}
+
+/// Returns the owning team of `repo`: its "team:" tag in the repos file, or the GitHub team with the most access.
+async fn owning_team(gh_client: &GitHubClient, entries: &[RepoEntry], repo: &str) -> String {
+    let tagged = entries
+        .iter()
+        .filter(|entry| entry.name() == repo)
+        .flat_map(RepoEntry::tags)
+        .find_map(|tag| tag.strip_prefix(TEAM_TAG));
+    if let Some(team) = tagged {
+        return team.to_string();
+    }
+
+    let url = format!("{}/repos/{repo}/teams?per_page=100", api_url());
+    let teams: Vec<Team> = match gh_client.send(gh_client.api_request(Method::GET, &url)).await {
+        Ok(resp) if resp.status().is_success() => resp.json().await.unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    teams
+        .into_iter()
+        .max_by_key(|team| permission_rank(&team.permission))
+        .map_or_else(|| UNOWNED.to_string(), |team| team.slug)
+}
+
+fn ticket_summary(package: &str, team: &str) -> String {
+    format!("Update {package} in {team} repositories")
+}
+
+/// Returns the ticket description in Jira wiki markup.
+fn ticket_description(package: &str, filename: &str, team: &str, findings: &[&Finding]) -> String {
+    let mut description = format!("A dependency audit found affected versions of *{package}* in {{{{{filename}}}}} of {team}'s repositories.\n\n");
+    description.push_str("||Repository||Version||Fix version||Advisories||\n");
+    for finding in findings {
+        let advisories: Vec<String> =
+            finding.advisories.iter().map(|advisory| format!("[{}|{}] ({})", advisory.ghsa_id, advisory.html_url, advisory.severity)).collect();
+        description.push_str(&format!(
+            "|{}|{}|{}|{}|\n",
+            finding.repo,
+            finding.version,
+            finding.fix_version.as_deref().unwrap_or("none published"),
+            if advisories.is_empty() { "-".to_string() } else { advisories.join(", ") }
+        ));
+    }
+    description.push_str("\n_This ticket is updated by ygg on each audit._\n");
+    description
+}
+
+/// Opens or updates one Jira ticket per owning team for the repositories the last run found an affected version
+/// of its package in. `entries` are the repos file entries, read for team tags. With `dry_run`, prints the
+/// tickets instead.
+pub(crate) async fn create_tickets(
+    gh_client: &GitHubClient,
+    config: Option<&JiraConfig>,
+    network: &NetworkOptions,
+    entries: &[RepoEntry],
+    fix_version: Option<&Version>,
+    dry_run: bool,
+) -> Result<()> {
+    let config = config.ok_or_else(|| YggError::Config("--jira needs a [jira] table with url and project in .ygg.toml".to_string()))?;
+    let jira = Jira { client: network.client_builder()?.build()?, config, token: if dry_run { String::new() } else { api_token(config)? } };
+
+    let findings::Findings { package, filename, findings } = findings::load(gh_client, fix_version).await?;
+    if findings.is_empty() {
+        eprintln!("No affected repositories in the last audit of {package}; no tickets to open.");
+        return Ok(());
+    }
+    let mut teams: BTreeMap<String, Vec<&Finding>> = BTreeMap::new();
+    for finding in &findings {
+        teams.entry(owning_team(gh_client, entries, &finding.repo).await).or_default().push(finding);
+    }
+
+    let (mut created, mut updated, mut unchanged, mut failed) = (0, 0, 0, 0);
+    for (team, findings) in &teams {
+        let summary = ticket_summary(&package, team);
+        let description = ticket_description(&package, &filename, team, findings);
+        if dry_run {
+            println!("{summary} ({} repositories)", findings.len());
+            continue;
+        }
+        match jira.file_ticket(&summary, &description).await {
+            Ok(Filed::Created(key)) => {
+                created += 1;
+                println!("created    {}  ({team})", jira.browse_url(&key));
+            }
+            Ok(Filed::Updated(key)) => {
+                updated += 1;
+                println!("updated    {}  ({team})", jira.browse_url(&key));
+            }
+            Ok(Filed::Unchanged(key)) => {
+                unchanged += 1;
+                println!("unchanged  {}  ({team})", jira.browse_url(&key));
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("{team}: {e}");
+            }
+        }
+    }
+
+    if dry_run {
+        eprintln!("\nDry run: would open or update {} tickets in {}", teams.len(), config.project);
+    } else {
+        eprintln!("\n{created} tickets created, {updated} updated, {unchanged} unchanged, {failed} failed");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn description_uses_wiki_markup() {
+        let finding = Finding { repo: "acme/api".to_string(), version: "4.17.20".to_string(), fix_version: None, advisories: Vec::new() };
+        let description = ticket_description("lodash", "package-lock.json", "payments", &[&finding]);
+        assert!(description.starts_with("A dependency audit found affected versions of *lodash* in {{package-lock.json}} of payments'"));
+        assert!(description.contains("|acme/api|4.17.20|none published|-|\n"));
+    }
+
+    #[test]
+    fn quotes_jql_strings() {
+        assert_eq!(jql_string(r#"say "hi" \o/"#), r#""say \"hi\" \\o/""#);
+    }
+}
@@ -0,0 +1,223 @@
+//! Low-memory package-lock.json parsing.
+//!
+//! Lockfiles can be tens of megabytes. Rather than deserializing every entry into a map, the parser walks the
+//! document once and only materializes the entries for the queried package, skipping everything else.
+
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+use std::fmt;
+
+#[derive(Deserialize, Debug)]
+struct Packages {
+    version: Option<String>,
+}
+
+/// The parts of a package-lock.json relevant to one package.
+#[derive(Default, Debug)]
+pub(crate) struct LockfileMatch {
+    pub(crate) lockfile_version: Option<i32>,
+    /// Version from the `packages` section (lockfile v2/v3), keyed by `node_modules/{name}`.
+    pub(crate) packages_version: Option<String>,
+    /// Version from the `dependencies` section (lockfile v1), keyed by `{name}`.
+    pub(crate) dependencies_version: Option<String>,
+}
+
+/// Parses `file_str`, extracting only the entries for `package`.
+pub(crate) fn find_package(file_str: &str, package: &str) -> serde_json::Result<LockfileMatch> {
+    let mut deserializer = serde_json::Deserializer::from_str(file_str);
+    let found = LockfileSeed { package }.deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(found)
+}
+
+struct LockfileSeed<'a> {
+    package: &'a str,
+}
+
+impl<'de> DeserializeSeed<'de> for LockfileSeed<'_> {
+    type Value = LockfileMatch;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for LockfileSeed<'_> {
+    type Value = LockfileMatch;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a package-lock.json object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let node_modules_key = format!("node_modules/{}", self.package);
+        let mut found = LockfileMatch::default();
+
+        while let Some(key) = map.next_key::<TopLevelKey>()? {
+            match key {
+                TopLevelKey::LockfileVersion => found.lockfile_version = map.next_value()?,
+                TopLevelKey::Packages => found.packages_version = map.next_value_seed(EntrySeed { key: &node_modules_key })?,
+                TopLevelKey::Dependencies => found.dependencies_version = map.next_value_seed(EntrySeed { key: self.package })?,
+                TopLevelKey::Other => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "camelCase")]
+enum TopLevelKey {
+    LockfileVersion,
+    Packages,
+    Dependencies,
+    #[serde(other)]
+    Other,
+}
+
+/// Visits a map of package entries, returning the version of the entry named `key` and skipping all others.
+struct EntrySeed<'a> {
+    key: &'a str,
+}
+
+impl<'de> DeserializeSeed<'de> for EntrySeed<'_> {
+    type Value = Option<String>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_option(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntrySeed<'_> {
+    type Value = Option<String>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map of package entries")
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut version = None;
+        while let Some(matches) = map.next_key_seed(KeyIs(self.key))? {
+            if matches {
+                version = map.next_value::<Packages>()?.version;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(version)
+    }
+}
+
+/// Compares a map key against a target without allocating, even for keys containing escapes.
+struct KeyIs<'a>(&'a str);
+
+impl<'de> DeserializeSeed<'de> for KeyIs<'_> {
+    type Value = bool;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeyIs<'_> {
+    type Value = bool;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a package name")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(v == self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_v1_dependencies() {
+        let lockfile = r#"{
+            "name": "app",
+            "lockfileVersion": 1,
+            "dependencies": {
+                "left-pad": { "version": "1.3.0", "requires": { "nothing": "^1.0.0" } },
+                "lodash": { "version": "4.17.20", "integrity": "sha512-..." }
+            }
+        }"#;
+        let found = find_package(lockfile, "lodash").unwrap();
+        assert_eq!(found.lockfile_version, Some(1));
+        assert_eq!(found.dependencies_version.as_deref(), Some("4.17.20"));
+        assert_eq!(found.packages_version, None);
+    }
+
+    #[test]
+    fn finds_v2_packages_and_dependencies() {
+        let lockfile = r#"{
+            "lockfileVersion": 2,
+            "packages": {
+                "": { "name": "app", "dependencies": { "lodash": "^4.17.0" } },
+                "node_modules/lodash": { "version": "4.17.21" },
+                "node_modules/other/node_modules/lodash": { "version": "3.10.1" }
+            },
+            "dependencies": { "lodash": { "version": "4.17.21" } }
+        }"#;
+        let found = find_package(lockfile, "lodash").unwrap();
+        assert_eq!(found.lockfile_version, Some(2));
+        assert_eq!(found.packages_version.as_deref(), Some("4.17.21"));
+        assert_eq!(found.dependencies_version.as_deref(), Some("4.17.21"));
+    }
+
+    #[test]
+    fn finds_v3_packages() {
+        let lockfile = r#"{"lockfileVersion": 3, "packages": {"node_modules/lodash": {"version": "4.17.21"}}}"#;
+        let found = find_package(lockfile, "lodash").unwrap();
+        assert_eq!(found.lockfile_version, Some(3));
+        assert_eq!(found.packages_version.as_deref(), Some("4.17.21"));
+        assert_eq!(found.dependencies_version, None);
+    }
+
+    #[test]
+    fn missing_package() {
+        let lockfile = r#"{"lockfileVersion": 3, "packages": {"node_modules/lodash-es": {"version": "4.17.21"}}}"#;
+        let found = find_package(lockfile, "lodash").unwrap();
+        assert_eq!(found.packages_version, None);
+        assert_eq!(found.dependencies_version, None);
+    }
+
+    #[test]
+    fn scoped_names() {
+        let lockfile = r#"{"lockfileVersion": 3, "packages": {"node_modules/@babel/core": {"version": "7.24.0"}}}"#;
+        assert_eq!(find_package(lockfile, "@babel/core").unwrap().packages_version.as_deref(), Some("7.24.0"));
+        assert_eq!(find_package(lockfile, "core").unwrap().packages_version, None);
+    }
+
+    #[test]
+    fn escaped_keys() {
+        let lockfile = r#"{"lockfileVersion": 3, "packages": {"node_modules\/@babel/core": {"version": "7.24.0"}}}"#;
+        assert_eq!(find_package(lockfile, "@babel/core").unwrap().packages_version.as_deref(), Some("7.24.0"));
+    }
+
+    #[test]
+    fn null_sections() {
+        let lockfile = r#"{"lockfileVersion": 1, "dependencies": null}"#;
+        assert_eq!(find_package(lockfile, "lodash").unwrap().dependencies_version, None);
+    }
+
+    #[test]
+    fn rejects_trailing_garbage() {
+        let lockfile = r#"{"lockfileVersion": 3, "packages": {}} trailing"#;
+        assert!(find_package(lockfile, "lodash").is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! Notifications of results: a summary card for Microsoft Teams (`ygg report --teams-webhook`), and the full
+//! results of a run for any endpoint (`--post-results`).
+//!
+//! Teams gets an Adaptive Card with the affected versions, the repositories using each, and links to the
+//! advisories. Posted results are signed like GitHub's webhooks when a secret is set: an
+//! "X-Ygg-Signature-256: sha256=<hex>" header with the HMAC-SHA256 of the body.
+
+use hmac::{Hmac, Mac};
+use semver::Version;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::env;
+
+use crate::findings::{self, Finding};
+use crate::{unix_now, Config, GitHubClient, NetworkOptions, Result, RunState, YggError};
+
+/// Repositories named per version before the rest are counted, keeping cards under Teams' 28 KB payload limit.
+const MAX_REPOS_PER_VERSION: usize = 15;
+
+/// Header carrying the signature of posted results.
+const SIGNATURE_HEADER: &str = "X-Ygg-Signature-256";
+
+/// Returns `value`, read from the environment if it starts with "$". `source` names the setting for errors.
+pub(crate) fn resolve_secret(value: &str, source: &str) -> Result<String> {
+    match value.strip_prefix('$') {
+        Some(var) => env::var(var)
+            .ok()
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| YggError::Config(format!("{source} names {var}, which is not set"))),
+        None => Ok(value.to_string()),
+    }
+}
+
+/// Returns the Adaptive Card message for the findings, one fact per affected version.
+fn teams_card(package: &str, filename: &str, findings: &[Finding]) -> Value {
+    let mut versions: BTreeMap<&str, Vec<&Finding>> = BTreeMap::new();
+    for finding in findings {
+        versions.entry(&finding.version).or_default().push(finding);
+    }
+
+    let mut body = vec![
+        json!({
+            "type": "TextBlock",
+            "text": format!("{package}: {} affected repositories", findings.len()),
+            "weight": "Bolder",
+            "size": "Medium",
+            "wrap": true,
+        }),
+        json!({
+            "type": "TextBlock",
+            "text": format!("The last dependency audit of {filename} found versions with advisories or below the fix version."),
+            "isSubtle": true,
+            "wrap": true,
+        }),
+    ];
+    let facts: Vec<Value> = versions
+        .iter()
+        .map(|(version, findings)| {
+            let mut repos: Vec<String> = findings.iter().take(MAX_REPOS_PER_VERSION).map(|f| f.repo.clone()).collect();
+            if findings.len() > MAX_REPOS_PER_VERSION {
+                repos.push(format!("and {} more", findings.len() - MAX_REPOS_PER_VERSION));
+            }
+            let fix = findings[0].fix_version.as_deref().map_or(String::new(), |fix| format!(" (fix: {fix})"));
+            json!({ "title": format!("{version}{fix}"), "value": repos.join(", ") })
+        })
+        .collect();
+    body.push(json!({ "type": "FactSet", "facts": facts }));
+
+    let mut advisories: Vec<(&str, &str, &str)> = findings
+        .iter()
+        .flat_map(|finding| &finding.advisories)
+        .map(|advisory| (advisory.ghsa_id.as_str(), advisory.severity.as_str(), advisory.html_url.as_str()))
+        .collect();
+    advisories.sort_unstable();
+    advisories.dedup();
+    let actions: Vec<Value> = advisories
+        .iter()
+        .filter(|(_, _, url)| !url.is_empty())
+        .map(|(id, severity, url)| json!({ "type": "Action.OpenUrl", "title": format!("{id} ({severity})"), "url": url }))
+        .collect();
+
+    json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "contentUrl": null,
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "msteams": { "width": "Full" },
+                "body": body,
+                "actions": actions,
+            },
+        }],
+    })
+}
+
+/// Posts the findings of the last run to the Teams incoming webhook at `webhook`, a URL or "$VAR" naming an
+/// environment variable holding one. Nothing is posted when no repository is affected. With `dry_run`, prints the
+/// card instead.
+pub(crate) async fn post_teams(
+    gh_client: &GitHubClient,
+    network: &NetworkOptions,
+    webhook: &str,
+    fix_version: Option<&Version>,
+    dry_run: bool,
+) -> Result<()> {
+    let url = resolve_secret(webhook, "--teams-webhook")?;
+    let findings::Findings { package, filename, findings } = findings::load(gh_client, fix_version).await?;
+    if findings.is_empty() {
+        eprintln!("No affected repositories in the last audit of {package}; nothing to post.");
+        return Ok(());
+    }
+
+    if dry_run {
+        println!("{}", serde_json::to_string_pretty(&teams_card(&package, &filename, &findings))?);
+        return Ok(());
+    }
+    send_teams(network, &url, &findings::Findings { package, filename, findings }).await
+}
+
+/// Posts the card for `findings` to the Teams incoming webhook at `url`.
+pub(crate) async fn send_teams(network: &NetworkOptions, url: &str, findings: &findings::Findings) -> Result<()> {
+    let card = teams_card(&findings.package, &findings.filename, &findings.findings);
+    let resp = network.client_builder()?.build()?.post(url).json(&card).send().await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(YggError::ApiError(format!("Teams webhook responded {status}: {}", body.trim())));
+    }
+    eprintln!("Posted {} affected repositories of {} to Teams", findings.findings.len(), findings.package);
+    Ok(())
+}
+
+/// Returns the secret posted results are signed with: YGG_WEBHOOK_SECRET, or "webhook_secret" in .ygg.toml.
+pub(crate) fn webhook_secret(config: &Config) -> Option<String> {
+    env::var("YGG_WEBHOOK_SECRET").ok().filter(|secret| !secret.is_empty()).or_else(|| config.webhook_secret.clone())
+}
+
+/// Returns the JSON posted for a run: the query, every repository's outcome, and the matches.
+pub(crate) fn results_report(state: &RunState, interrupted: bool) -> Value {
+    json!({
+        "query": state.query,
+        "filename": state.filename,
+        "finished_at": unix_now(),
+        "interrupted": interrupted,
+        "repos": state.repos,
+        "found": state.found().map(|(repo, value)| json!({ "repo": repo, "value": value })).collect::<Vec<_>>(),
+    })
+}
+
+/// Returns the signature header value for `body`: "sha256=" and the hex HMAC-SHA256 keyed with `secret`.
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    let digest: String = mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256={digest}")
+}
+
+/// Checks a GitHub webhook delivery's "X-Hub-Signature-256" header, "sha256=<hex>", against `body`, in constant
+/// time.
+pub(crate) fn verify_signature(secret: &str, body: &[u8], header: &str) -> bool {
+    let Some(hex) = header.strip_prefix("sha256=").filter(|hex| hex.len() == 64 && hex.is_ascii()) else {
+        return false;
+    };
+    let Ok(expected) = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16)).collect::<std::result::Result<Vec<u8>, _>>() else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+/// POSTs `report` as JSON to `url`, signed with `secret` ("$VAR" reads it from the environment) when given.
+pub(crate) async fn post_results(network: &NetworkOptions, url: &str, secret: Option<&str>, report: &Value) -> Result<()> {
+    let body = serde_json::to_vec(report)?;
+    let mut request = network
+        .client_builder()?
+        .build()?
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(reqwest::header::USER_AGENT, "ygg");
+    if let Some(secret) = secret {
+        request = request.header(SIGNATURE_HEADER, signature(&resolve_secret(secret, "webhook_secret")?, &body));
+    }
+    let resp = request.body(body).send().await?;
+    if !resp.status().is_success() {
+        return Err(YggError::ApiError(format!("{url} responded {}", resp.status())));
+    }
+    Ok(())
+}
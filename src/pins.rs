@@ -0,0 +1,139 @@
+//! Pinned commit SHAs for reproducible runs.
+//!
+//! `--pin` resolves the commit each repository's ref (or default branch) points at and records it in
+//! `ygg-pins.json`; `--pinned` reads files at exactly those commits, so a compliance report can be regenerated
+//! later with the same inputs even after the branches have moved on.
+
+use futures::prelude::*;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::repolist::RepoEntry;
+use crate::{api_url, progress_bar, unix_now, GitHubClient, Result, YggError};
+
+pub(crate) const PINS_FILE: &str = "ygg-pins.json";
+
+/// The commits a run was pinned to.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub(crate) struct Pins {
+    /// When the SHAs were resolved, in seconds since the Unix epoch.
+    pub(crate) pinned_at: u64,
+    pub(crate) repos: BTreeMap<String, Pin>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct Pin {
+    /// The ref that was resolved, if not the default branch.
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) git_ref: Option<String>,
+    pub(crate) sha: String,
+}
+
+impl Pins {
+    pub(crate) fn load() -> Result<Self> {
+        let data = fs::read_to_string(PINS_FILE)
+            .map_err(|e| YggError::Config(format!("can't read {PINS_FILE} (create it with --pin): {e}")))?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    pub(crate) fn save(&self) -> Result<()> {
+        fs::write(PINS_FILE, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Points each entry at its pinned commit, looked up by `entry.key(by_ref)`. Entries without a pin keep their
+    /// ref, with a warning.
+    pub(crate) fn apply(&self, entries: Vec<RepoEntry>, by_ref: bool) -> Vec<RepoEntry> {
+        let mut unpinned = Vec::new();
+        let entries = entries
+            .into_iter()
+            .map(|entry| match self.repos.get(&entry.key(by_ref)) {
+                Some(pin) => entry.with_ref(&pin.sha),
+                None => {
+                    unpinned.push(entry.key(by_ref));
+                    entry
+                }
+            })
+            .collect();
+        if !unpinned.is_empty() {
+            eprintln!("Warning: {} repositories have no pin in {PINS_FILE} and are read at their current ref:", unpinned.len());
+            for repo in unpinned {
+                eprintln!("  {repo}");
+            }
+        }
+        entries
+    }
+}
+
+/// Resolves the commit SHA `git_ref` (or the default branch) points at in `repo`.
+pub(crate) async fn resolve_sha(gh_client: &GitHubClient, repo: &str, git_ref: Option<&str>) -> Result<String> {
+    let url = format!("{}/repos/{repo}/commits/{}", api_url(), git_ref.unwrap_or("HEAD"));
+    let request = gh_client.api_request(Method::GET, &url).header("Accept", "application/vnd.github.sha");
+    let resp = gh_client.send(request).await?;
+
+    if resp.status() == reqwest::StatusCode::NOT_FOUND || resp.status() == reqwest::StatusCode::UNPROCESSABLE_ENTITY {
+        return Err(YggError::NotFound);
+    }
+    if !resp.status().is_success() {
+        return Err(YggError::UnexpectedStatus(resp.status()));
+    }
+    Ok(resp.text().await?.trim().to_string())
+}
+
+/// Resolves the current commit of every entry at `refs[i]`, at most `parallel` at a time, and returns the pins,
+/// keyed by `entry.key(by_ref)`. Repositories whose ref can't be resolved are left out with a warning.
+pub(crate) async fn resolve(
+    gh_client: &GitHubClient,
+    entries: &[RepoEntry],
+    refs: &[Option<&str>],
+    by_ref: bool,
+    parallel: usize,
+) -> Result<Pins> {
+    let pb = progress_bar(entries.len() as u64, "{msg} [{bar:40.cyan/blue}] {pos}/{len}");
+    pb.set_message("Resolving commits to pin");
+    let mut resolved = stream::iter(entries.iter().zip(refs))
+        .map(|(entry, &git_ref)| async move { (entry.key(by_ref), git_ref, resolve_sha(gh_client, entry.name(), git_ref).await) })
+        .buffer_unordered(parallel);
+
+    let mut pins = Pins { pinned_at: unix_now(), repos: BTreeMap::new() };
+    while let Some((repo, git_ref, sha)) = resolved.next().await {
+        pb.inc(1);
+        match sha {
+            Ok(sha) => {
+                pins.repos.insert(repo, Pin { git_ref: git_ref.map(str::to_string), sha });
+            }
+            Err(YggError::NotFound) => {
+                pb.suspend(|| eprintln!("Warning: can't pin {repo}: {} not found", git_ref.unwrap_or("default branch")));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    pb.finish_and_clear();
+    Ok(pins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_pins_by_branch() {
+        let pin = |sha: &str| Pin { git_ref: None, sha: sha.to_string() };
+        let pins = Pins {
+            pinned_at: 0,
+            repos: BTreeMap::from([("acme/api@release/1".to_string(), pin("1111")), ("acme/api@release/2".to_string(), pin("2222"))]),
+        };
+        let entry = RepoEntry::Name("acme/api".to_string());
+        let pinned = pins.apply(vec![entry.with_ref("release/1"), entry.with_ref("release/2")], true);
+        assert_eq!(pinned.iter().map(RepoEntry::git_ref).collect::<Vec<_>>(), [Some("1111"), Some("2222")]);
+    }
+
+    #[test]
+    fn applies_pins_by_name() {
+        let pins = Pins { pinned_at: 0, repos: BTreeMap::from([("acme/api".to_string(), Pin { git_ref: None, sha: "1111".to_string() })]) };
+        let pinned = pins.apply(vec![RepoEntry::Name("acme/api".to_string()), RepoEntry::Name("acme/web".to_string())], false);
+        assert_eq!(pinned.iter().map(RepoEntry::git_ref).collect::<Vec<_>>(), [Some("1111"), None]);
+    }
+}
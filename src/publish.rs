@@ -0,0 +1,279 @@
+//! Publishing the report of a run (`--publish`).
+//!
+//! The report lists the repositories with a match, sorted by version in a package audit, followed by the
+//! repositories that failed or were blocked. It is rendered as markdown and as a standalone HTML page:
+//! - `gist` uploads both to a secret gist, which needs a token with the "gist" scope.
+//! - `s3://bucket/prefix/` uploads the HTML page and the JSON `--post-results` sends, under keys with the query
+//!   and a UTC timestamp (e.g. "prefix/ygg-lodash-20240301T020000Z.json"), so runs accumulate rather than
+//!   overwrite. Credentials come from the environment, as for the remote cache.
+//! - `github:owner/repo[/dir]` commits the markdown report and the JSON results to `dir` on the repository's
+//!   default branch through the contents API. The paths are the same every run, so the repository's history
+//!   holds every version.
+
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use reqwest::{Method, StatusCode};
+use semver::Version;
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::cache::RemoteCache;
+use crate::{api_url, notify, GitHubClient, NetworkOptions, RepoState, Result, RunState, YggError};
+
+/// Where a report is published.
+#[derive(Clone, Debug)]
+pub(crate) enum Target {
+    Gist,
+    /// An S3 (or S3-compatible) location, "s3://bucket[/prefix]".
+    S3(String),
+    /// A repository to commit reports to, and the directory in it.
+    Repository { repo: String, dir: String },
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "gist" => Ok(Self::Gist),
+            _ if s.strip_prefix("s3://").is_some_and(|rest| !rest.is_empty() && !rest.starts_with('/')) => Ok(Self::S3(s.to_string())),
+            _ if s.starts_with("github:") => {
+                let mut parts = s["github:".len()..].splitn(3, '/');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(owner), Some(name), dir) if !owner.is_empty() && !name.is_empty() => {
+                        Ok(Self::Repository { repo: format!("{owner}/{name}"), dir: dir.unwrap_or_default().trim_matches('/').to_string() })
+                    }
+                    _ => Err(format!("publish target {s:?} must look like github:owner/repo or github:owner/repo/dir")),
+                }
+            }
+            _ => Err(format!("unknown publish target {s:?}, expected \"gist\", \"s3://bucket/prefix/\" or \"github:owner/repo/dir\"")),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Gist {
+    html_url: String,
+}
+
+#[derive(Deserialize)]
+struct RepositoryFile {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct CommittedFile {
+    content: Committed,
+}
+
+#[derive(Deserialize)]
+struct Committed {
+    html_url: String,
+}
+
+/// A run's results, arranged for rendering.
+struct Report<'a> {
+    state: &'a RunState,
+    is_package_lock: bool,
+    interrupted: bool,
+    generated_at: DateTime<Utc>,
+    /// Matches as (repository, value).
+    found: Vec<(&'a str, &'a str)>,
+    /// Repositories that failed or were blocked, with why.
+    problems: Vec<(&'a str, &'a str)>,
+    not_found: usize,
+}
+
+impl<'a> Report<'a> {
+    fn new(state: &'a RunState, is_package_lock: bool, interrupted: bool) -> Self {
+        let mut found: Vec<(&str, &str)> = state.found().collect();
+        if is_package_lock {
+            found.sort_by_key(|(repo, value)| (Version::parse(value).unwrap_or(Version::new(0, 0, 0)), *repo));
+        }
+        let problems: Vec<(&str, &str)> = state
+            .repos
+            .iter()
+            .filter_map(|(repo, repo_state)| match repo_state {
+                RepoState::Blocked { reason } => Some((repo.as_str(), reason.as_str())),
+                RepoState::Failed { error } => Some((repo.as_str(), error.as_str())),
+                _ => None,
+            })
+            .collect();
+        let not_found = state.repos.len() - found.len() - problems.len();
+        let generated_at = Utc::now();
+        Self { state, is_package_lock, interrupted, generated_at, found, problems, not_found }
+    }
+
+    fn title(&self) -> String {
+        if self.is_package_lock {
+            format!("ygg audit of {}", self.state.query)
+        } else {
+            format!("ygg search for {:?}", self.state.query)
+        }
+    }
+
+    fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} of {} repositories matched in {}; {} had no match, {} failed or were blocked.",
+            self.found.len(),
+            self.state.repos.len(),
+            self.state.filename,
+            self.not_found,
+            self.problems.len()
+        );
+        if self.interrupted {
+            summary.push_str(" The run was interrupted, so these results are partial.");
+        }
+        summary
+    }
+
+    fn value_heading(&self) -> &str {
+        if self.is_package_lock {
+            "Version"
+        } else {
+            "Match"
+        }
+    }
+
+    fn markdown(&self) -> String {
+        let mut out = format!("# {}\n\n{}\n\n", self.title(), self.summary());
+        if !self.found.is_empty() {
+            let _ = writeln!(out, "| Repository | {} |\n|---|---|", self.value_heading());
+            for (repo, value) in &self.found {
+                let _ = writeln!(out, "| {repo} | `{}` |", value.replace('|', "\\|"));
+            }
+        }
+        if !self.problems.is_empty() {
+            out.push_str("\n**Failed or blocked**\n\n");
+            for (repo, why) in &self.problems {
+                let _ = writeln!(out, "- {repo}: {why}");
+            }
+        }
+        let _ = write!(out, "\n_Generated by ygg at {}._\n", self.generated_at.format("%Y-%m-%d %H:%M UTC"));
+        out
+    }
+
+    fn html(&self) -> String {
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n\
+             body {{ font-family: system-ui, sans-serif; margin: 2rem auto; max-width: 60rem; color: #1f2328; }}\n\
+             table {{ border-collapse: collapse; }}\n\
+             th, td {{ border: 1px solid #d0d7de; padding: 0.3rem 0.8rem; text-align: left; }}\n\
+             code {{ font-size: 0.9em; }}\n\
+             </style>\n</head>\n<body>\n<h1>{title}</h1>\n<p>{summary}</p>\n",
+            title = escape(&self.title()),
+            summary = escape(&self.summary())
+        );
+        if !self.found.is_empty() {
+            let _ = writeln!(out, "<table>\n<tr><th>Repository</th><th>{}</th></tr>", self.value_heading());
+            for (repo, value) in &self.found {
+                let _ = writeln!(out, "<tr><td>{}</td><td><code>{}</code></td></tr>", escape(repo), escape(value));
+            }
+            out.push_str("</table>\n");
+        }
+        if !self.problems.is_empty() {
+            out.push_str("<h2>Failed or blocked</h2>\n<ul>\n");
+            for (repo, why) in &self.problems {
+                let _ = writeln!(out, "<li>{}: {}</li>", escape(repo), escape(why));
+            }
+            out.push_str("</ul>\n");
+        }
+        let _ = write!(out, "<p><em>Generated by ygg at {}.</em></p>\n</body>\n</html>\n", self.generated_at.format("%Y-%m-%d %H:%M UTC"));
+        out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Returns the file name reports of `query` are published under, without an extension.
+fn report_name(query: &str) -> String {
+    let slug: String = query.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' }).collect();
+    format!("ygg-{}", slug.trim_matches('-'))
+}
+
+/// Publishes the report of a run to `target` and returns where it can be read.
+pub(crate) async fn publish(
+    gh_client: &GitHubClient,
+    network: &NetworkOptions,
+    target: &Target,
+    state: &RunState,
+    is_package_lock: bool,
+    interrupted: bool,
+) -> Result<String> {
+    let report = Report::new(state, is_package_lock, interrupted);
+    match target {
+        Target::Gist => gist(gh_client, &report).await,
+        Target::S3(location) => s3(network, location, &report).await,
+        Target::Repository { repo, dir } => repository(gh_client, repo, dir, &report).await,
+    }
+}
+
+/// Uploads the markdown and HTML reports to a new secret gist.
+async fn gist(gh_client: &GitHubClient, report: &Report<'_>) -> Result<String> {
+    let name = report_name(&report.state.query);
+    let body = json!({
+        "description": report.title(),
+        "public": false,
+        "files": {
+            format!("{name}.md"): { "content": report.markdown() },
+            format!("{name}.html"): { "content": report.html() },
+        },
+    });
+    let resp = gh_client.send(gh_client.api_request(Method::POST, &format!("{}/gists", api_url())).json(&body)).await?;
+    match resp.status() {
+        status if status.is_success() => Ok(resp.json::<Gist>().await?.html_url),
+        // Tokens without the gist scope, and GitHub Apps, which can't own gists
+        reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::NOT_FOUND => {
+            Err(YggError::ApiError("creating a gist needs a personal access token with the \"gist\" scope".to_string()))
+        }
+        status => Err(YggError::UnexpectedStatus(status)),
+    }
+}
+
+/// Uploads the JSON results and HTML report under timestamped keys.
+async fn s3(network: &NetworkOptions, location: &str, report: &Report<'_>) -> Result<String> {
+    let remote = RemoteCache::open(location, network)?;
+    let name = format!("{}-{}", report_name(&report.state.query), report.generated_at.format("%Y%m%dT%H%M%SZ"));
+    let results = serde_json::to_vec_pretty(&notify::results_report(report.state, report.interrupted))?;
+    remote.upload(&format!("{name}.json"), results, "application/json").await?;
+    remote.upload(&format!("{name}.html"), report.html().into_bytes(), "text/html; charset=utf-8").await?;
+    Ok(format!("{}/{name}.{{json,html}}", location.trim_end_matches('/')))
+}
+
+/// Creates or updates `path` in `repo` on its default branch, returning the file's URL.
+async fn put_file(gh_client: &GitHubClient, repo: &str, path: &str, content: &[u8], message: &str) -> Result<String> {
+    let url = format!("{}/repos/{repo}/contents/{path}", api_url());
+    let resp = gh_client.send(gh_client.api_request(Method::GET, &url)).await?;
+    // Updating a file needs the blob it replaces
+    let sha = match resp.status() {
+        StatusCode::NOT_FOUND => None,
+        status if status.is_success() => Some(resp.json::<RepositoryFile>().await?.sha),
+        status => return Err(YggError::UnexpectedStatus(status)),
+    };
+    let mut body = json!({ "message": message, "content": base64::engine::general_purpose::STANDARD.encode(content) });
+    if let Some(sha) = sha {
+        body["sha"] = json!(sha);
+    }
+    let resp = gh_client.send(gh_client.api_request(Method::PUT, &url).json(&body)).await?;
+    match resp.status() {
+        status if status.is_success() => Ok(resp.json::<CommittedFile>().await?.content.html_url),
+        StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => {
+            Err(YggError::ApiError(format!("committing to {repo} needs a token with write access to its contents")))
+        }
+        status => Err(YggError::UnexpectedStatus(status)),
+    }
+}
+
+/// Commits the markdown report and JSON results to `dir` in `repo`.
+async fn repository(gh_client: &GitHubClient, repo: &str, dir: &str, report: &Report<'_>) -> Result<String> {
+    let name = report_name(&report.state.query);
+    let path = |extension: &str| if dir.is_empty() { format!("{name}.{extension}") } else { format!("{dir}/{name}.{extension}") };
+    let message = format!("{}: {} of {} repositories matched", report.title(), report.found.len(), report.state.repos.len());
+    let results = serde_json::to_vec_pretty(&notify::results_report(report.state, report.interrupted))?;
+    put_file(gh_client, repo, &path("json"), &results, &message).await?;
+    put_file(gh_client, repo, &path("md"), report.markdown().as_bytes(), &message).await
+}
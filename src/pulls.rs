@@ -0,0 +1,204 @@
+//! Pull request audits (`ygg pr-comment`).
+//!
+//! Reads package-lock.json at a pull request's head and base commits, looks up the advisories of the version the
+//! head would merge, and posts the result as a comment on the pull request. The comment carries a hidden marker,
+//! so each push's rerun edits it in place instead of adding another, and `--fail` turns ygg into a required check
+//! for lockfile changes.
+
+use reqwest::{Method, StatusCode};
+use semver::Version;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::cache::CacheManager;
+use crate::{api_url, contents_url, fetch_advisories, findings, process_package_lock, Advisory, GitHubClient, Result, YggError};
+
+#[derive(Deserialize)]
+struct PullRequest {
+    head: Commit,
+    base: Commit,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    sha: String,
+    /// Null when the head's fork has been deleted.
+    repo: Option<Repository>,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    full_name: String,
+}
+
+#[derive(Deserialize)]
+struct Comment {
+    id: u64,
+    html_url: String,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+/// The pull request to audit and where its lockfile is.
+pub(crate) struct Target<'a> {
+    pub(crate) repo: &'a str,
+    pub(crate) number: u64,
+    /// Directory of package-lock.json, if not the repository root.
+    pub(crate) dir: Option<&'a str>,
+}
+
+/// What a pull request does to the package.
+struct Audit {
+    base_version: Option<String>,
+    head_version: Option<String>,
+    advisories: Vec<Advisory>,
+    fix_version: Option<String>,
+}
+
+impl Audit {
+    /// Whether the head's version has advisories or is below the fix version.
+    fn is_affected(&self, fix_version: Option<&Version>) -> bool {
+        let below_fix = match (&self.head_version, fix_version) {
+            (Some(version), Some(fix)) => Version::parse(version).is_ok_and(|v| v < *fix),
+            _ => false,
+        };
+        !self.advisories.is_empty() || below_fix
+    }
+}
+
+/// Returns the hidden marker that identifies the comment for `package`.
+fn marker(package: &str) -> String {
+    format!("<!-- ygg:pull-request {package} -->")
+}
+
+fn comment_body(package: &str, path: &str, audit: &Audit, affected: bool, head_sha: &str) -> String {
+    let version = |v: &Option<String>| v.as_deref().map_or("not installed".to_string(), |v| format!("`{v}`"));
+    let mut body = format!("{}\n", marker(package));
+    let verdict = match (&audit.head_version, affected) {
+        (None, _) => format!(":white_check_mark: **{package}** is not in `{path}`."),
+        (Some(_), false) => format!(":white_check_mark: **{package}** has no known vulnerabilities at this version."),
+        (Some(_), true) => format!(":x: **{package}** needs an update."),
+    };
+    body.push_str(&format!("### ygg audit\n\n{verdict}\n\n"));
+    body.push_str("| | |\n|---|---|\n");
+    body.push_str(&format!("| Base | {} |\n", version(&audit.base_version)));
+    body.push_str(&format!("| This pull request | {} |\n", version(&audit.head_version)));
+    if let Some(fix) = &audit.fix_version {
+        body.push_str(&format!("| Fix version | `{fix}` |\n"));
+    }
+    if !audit.advisories.is_empty() {
+        body.push_str("\n**Advisories**\n\n");
+        body.push_str(&findings::advisory_list(&audit.advisories));
+    }
+    body.push_str(&format!("\n_Audited at {head_sha}; this comment is updated on each run._\n"));
+    body
+}
+
+/// Returns the version of `package` in the lockfile at `path` in `repo` at `sha`, or None if the lockfile or the
+/// package is missing.
+async fn locked_version(
+    gh_client: &GitHubClient,
+    cache_manager: &CacheManager,
+    repo: &str,
+    path: &str,
+    sha: &str,
+    package: &str,
+) -> Result<Option<String>> {
+    match gh_client.fetch_raw_file(&contents_url(repo, path, Some(sha)), cache_manager).await {
+        Ok(fetched) => {
+            let version = process_package_lock(&String::from_utf8_lossy(&fetched.body), package);
+            Ok(Some(version).filter(|version| version != "-------"))
+        }
+        Err(YggError::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the comment posted for `package` on the pull request earlier, if any.
+async fn find_comment(gh_client: &GitHubClient, repo: &str, number: u64, package: &str) -> Result<Option<Comment>> {
+    let marker = marker(package);
+    let mut page = 1;
+    loop {
+        let url = format!("{}/repos/{repo}/issues/{number}/comments?per_page=100&page={page}", api_url());
+        let resp = gh_client.send(gh_client.api_request(Method::GET, &url)).await?;
+        if !resp.status().is_success() {
+            return Err(YggError::UnexpectedStatus(resp.status()));
+        }
+        let comments: Vec<Comment> = resp.json().await?;
+        let last_page = comments.len() < 100;
+        if let Some(comment) = comments.into_iter().find(|c| c.body.as_deref().is_some_and(|body| body.contains(&marker))) {
+            return Ok(Some(comment));
+        }
+        if last_page {
+            return Ok(None);
+        }
+        page += 1;
+    }
+}
+
+/// Audits the pull request for `package` and posts or updates the comment. Returns whether the pull request is
+/// affected. With `dry_run`, prints the comment instead.
+pub(crate) async fn comment(
+    gh_client: &GitHubClient,
+    cache_manager: &CacheManager,
+    target: &Target<'_>,
+    package: &str,
+    fix_version: Option<&Version>,
+    dry_run: bool,
+) -> Result<bool> {
+    let Target { repo, number, dir } = *target;
+    let url = format!("{}/repos/{repo}/pulls/{number}", api_url());
+    let resp = gh_client.send(gh_client.api_request(Method::GET, &url)).await?;
+    if resp.status() == StatusCode::NOT_FOUND {
+        return Err(YggError::Config(format!("{repo} has no pull request #{number}")));
+    }
+    if !resp.status().is_success() {
+        return Err(YggError::UnexpectedStatus(resp.status()));
+    }
+    let pull: PullRequest = resp.json().await?;
+    let head_repo = pull.head.repo.map(|r| r.full_name).ok_or_else(|| {
+        YggError::ApiError(format!("the head repository of #{number} was deleted"))
+    })?;
+    let base_repo = pull.base.repo.map_or_else(|| repo.to_string(), |r| r.full_name);
+
+    let path = match dir.map(|dir| dir.trim_matches('/')).filter(|dir| !dir.is_empty()) {
+        Some(dir) => format!("{dir}/package-lock.json"),
+        None => "package-lock.json".to_string(),
+    };
+    let head_version = locked_version(gh_client, cache_manager, &head_repo, &path, &pull.head.sha, package).await?;
+    let base_version = locked_version(gh_client, cache_manager, &base_repo, &path, &pull.base.sha, package).await?;
+    let advisories = match &head_version {
+        Some(version) => fetch_advisories(gh_client, package, version).await?,
+        None => Vec::new(),
+    };
+    let audit = Audit {
+        fix_version: fix_version.map(Version::to_string).or_else(|| findings::patched_version(package, &advisories)),
+        base_version,
+        head_version,
+        advisories,
+    };
+    let affected = audit.is_affected(fix_version);
+    let body = comment_body(package, &path, &audit, affected, &pull.head.sha);
+    cache_manager.finish().await;
+
+    if dry_run {
+        println!("{body}");
+        return Ok(affected);
+    }
+    let (method, url) = match find_comment(gh_client, repo, number, package).await? {
+        Some(comment) if comment.body.as_deref() == Some(body.as_str()) => {
+            println!("unchanged  {}", comment.html_url);
+            return Ok(affected);
+        }
+        Some(comment) => (Method::PATCH, format!("{}/repos/{repo}/issues/comments/{}", api_url(), comment.id)),
+        None => (Method::POST, format!("{}/repos/{repo}/issues/{number}/comments", api_url())),
+    };
+    let verb = if method == Method::POST { "commented" } else { "updated  " };
+    let resp = gh_client.send(gh_client.api_request(method, &url).json(&json!({ "body": body }))).await?;
+    if !resp.status().is_success() {
+        return Err(YggError::UnexpectedStatus(resp.status()));
+    }
+    let comment: Comment = resp.json().await?;
+    println!("{verb}  {}", comment.html_url);
+    Ok(affected)
+}
@@ -0,0 +1,308 @@
+//! Repository list files.
+//!
+//! A list is a JSON array of "owner/repo" names (comments and trailing commas allowed), plain text with one name
+//! per line, where blank lines and anything after a "#" are ignored, or, for ".yaml" and ".yml" files, a YAML
+//! sequence. JSON and plain text are told apart by their contents, so lists emitted by other tooling work without
+//! conversion.
+//!
+//! JSON and YAML entries may also be objects that override where the file is read for that repository:
+//! `{ "repo": "org/name", "ref": "release", "path": "apps/web", "tags": ["tier1"] }`. Tags group repositories
+//! so one list can serve several audiences (see --group). Names may be patterns such as `acme/payments-*`, which
+//! match the owner's repositories at run time.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::Result;
+
+/// Format of a repository list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Json,
+    Text,
+    Yaml,
+}
+
+/// An entry in a repository list: a bare name, or an object with per-repository overrides. Unknown keys are
+/// ignored, so inventories can carry their own fields.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub(crate) enum RepoEntry {
+    Name(String),
+    Detailed {
+        repo: String,
+        /// Branch, tag or SHA to read the file at instead of the default branch.
+        #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+        git_ref: Option<String>,
+        /// Directory the file is in, relative to the repository root (e.g., "apps/web" in a monorepo).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tags: Vec<String>,
+    },
+}
+
+impl RepoEntry {
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            Self::Name(name) | Self::Detailed { repo: name, .. } => name,
+        }
+    }
+
+    /// Returns true if the name is a pattern such as "acme/payments-*", expanded against the owner's repositories.
+    pub(crate) fn is_pattern(&self) -> bool {
+        self.name().contains(['*', '?'])
+    }
+
+    /// Returns a copy of this entry, with its overrides, for the repository `name`.
+    pub(crate) fn with_name(&self, name: &str) -> Self {
+        match self {
+            Self::Name(_) => Self::Name(name.to_string()),
+            Self::Detailed { git_ref, path, tags, .. } => Self::Detailed {
+                repo: name.to_string(),
+                git_ref: git_ref.clone(),
+                path: path.clone(),
+                tags: tags.clone(),
+            },
+        }
+    }
+
+    /// Returns a copy of this entry, with its other overrides, reading the file at `git_ref`.
+    pub(crate) fn with_ref(&self, git_ref: &str) -> Self {
+        match self {
+            Self::Name(repo) => Self::Detailed { repo: repo.clone(), git_ref: Some(git_ref.to_string()), path: None, tags: Vec::new() },
+            Self::Detailed { repo, path, tags, .. } => Self::Detailed {
+                repo: repo.clone(),
+                git_ref: Some(git_ref.to_string()),
+                path: path.clone(),
+                tags: tags.clone(),
+            },
+        }
+    }
+
+    pub(crate) fn git_ref(&self) -> Option<&str> {
+        match self {
+            Self::Name(_) => None,
+            Self::Detailed { git_ref, .. } => git_ref.as_deref().filter(|git_ref| !git_ref.is_empty()),
+        }
+    }
+
+    /// Returns the name the entry's results and pins are recorded under: its repository name, or "name@ref" with
+    /// `by_ref`, for runs that read several refs of one repository (--branches).
+    pub(crate) fn key(&self, by_ref: bool) -> String {
+        match self.git_ref() {
+            Some(git_ref) if by_ref => format!("{}@{git_ref}", self.name()),
+            _ => self.name().to_string(),
+        }
+    }
+
+    pub(crate) fn tags(&self) -> &[String] {
+        match self {
+            Self::Name(_) => &[],
+            Self::Detailed { tags, .. } => tags,
+        }
+    }
+
+    /// Returns the path of `filename` in this repository, under the entry's directory if it has one.
+    pub(crate) fn file_path(&self, filename: &str) -> String {
+        match self {
+            Self::Detailed { path: Some(dir), .. } if !dir.trim_matches('/').is_empty() => {
+                format!("{}/{filename}", dir.trim_matches('/'))
+            }
+            _ => filename.to_string(),
+        }
+    }
+}
+
+impl Format {
+    /// Returns the format of the list at `path`: YAML by extension, otherwise detected from `data`.
+    pub(crate) fn of(path: &str, data: &str) -> Self {
+        if path.ends_with(".yaml") || path.ends_with(".yml") {
+            Self::Yaml
+        } else {
+            Self::detect(data)
+        }
+    }
+
+    /// Detects the format of a list: JSON arrays start with "[" (after any comments), anything else is plain text.
+    pub(crate) fn detect(data: &str) -> Self {
+        if skip_json_comments(data).starts_with('[') {
+            Self::Json
+        } else {
+            Self::Text
+        }
+    }
+
+    /// Writes `repos` as a list in this format. Plain text lists can't hold overrides, so only names are written.
+    pub(crate) fn render(self, repos: &[RepoEntry]) -> Result<String> {
+        match self {
+            Self::Json => Ok(serde_json::to_string_pretty(repos)?),
+            Self::Text => Ok(repos.iter().map(|repo| format!("{}\n", repo.name())).collect()),
+            Self::Yaml => Ok(serde_yaml::to_string(repos)?),
+        }
+    }
+}
+
+/// Skips leading whitespace and `//` or `/* */` comments.
+fn skip_json_comments(mut data: &str) -> &str {
+    loop {
+        data = data.trim_start();
+        if let Some(rest) = data.strip_prefix("//") {
+            data = rest.split_once('\n').map_or("", |(_, rest)| rest);
+        } else if let Some(rest) = data.strip_prefix("/*") {
+            data = rest.split_once("*/").map_or("", |(_, rest)| rest);
+        } else {
+            return data;
+        }
+    }
+}
+
+/// Returns the repository named on a plain text line, if any.
+fn text_entry(line: &str) -> Option<&str> {
+    let entry = line.split('#').next().unwrap_or_default().trim();
+    (!entry.is_empty()).then_some(entry)
+}
+
+/// Returns the repository named on a YAML list line, e.g. `- org/repo` or `- repo: "org/repo"`, if any.
+fn yaml_entry(line: &str) -> Option<&str> {
+    let entry = text_entry(line)?.trim_start_matches('-').trim_start();
+    let entry = entry.strip_prefix("repo:").unwrap_or(entry).trim();
+    let entry = entry.trim_matches(|c| c == '"' || c == '\'');
+    entry.contains('/').then_some(entry)
+}
+
+/// Parses a repository list in the given format.
+pub(crate) fn parse(format: Format, data: &str) -> Result<Vec<RepoEntry>> {
+    match format {
+        Format::Json => Ok(json5::from_str(data)?),
+        Format::Text => Ok(data.lines().filter_map(text_entry).map(|repo| RepoEntry::Name(repo.to_string())).collect()),
+        Format::Yaml => Ok(serde_yaml::from_str(data)?),
+    }
+}
+
+/// Replaces renamed repositories in a list, dropping entries that now duplicate an earlier one. Plain text lists
+/// keep their comments and layout. JSON lists with comments or objects, and YAML lists, are edited in place too, so
+/// duplicates are left there.
+pub(crate) fn rename(format: Format, data: &str, renames: &BTreeMap<&str, &str>) -> Result<String> {
+    let mut seen = HashSet::new();
+    match format {
+        Format::Json => {
+            let Ok(repos) = serde_json::from_str::<Vec<String>>(data) else {
+                // Re-serializing would lose comments and formatting, so replace the quoted names instead
+                let mut updated = data.to_string();
+                for (old_name, new_name) in renames {
+                    updated = updated.replace(&format!("\"{old_name}\""), &format!("\"{new_name}\""));
+                }
+                return Ok(updated);
+            };
+            let updated: Vec<String> = repos
+                .into_iter()
+                .map(|repo| renames.get(repo.as_str()).map_or(repo, |new_name| new_name.to_string()))
+                .filter(|repo| seen.insert(repo.clone()))
+                .collect();
+            Ok(serde_json::to_string_pretty(&updated)?)
+        }
+        Format::Text => {
+            let mut updated = String::new();
+            for line in data.lines() {
+                match text_entry(line) {
+                    Some(repo) => {
+                        let new_name = renames.get(repo).copied().unwrap_or(repo);
+                        if seen.insert(new_name.to_string()) {
+                            updated.push_str(&line.replacen(repo, new_name, 1));
+                            updated.push('\n');
+                        }
+                    }
+                    None => {
+                        updated.push_str(line);
+                        updated.push('\n');
+                    }
+                }
+            }
+            Ok(updated)
+        }
+        Format::Yaml => {
+            let mut updated = String::new();
+            for line in data.lines() {
+                match yaml_entry(line).and_then(|repo| Some((repo, *renames.get(repo)?))) {
+                    Some((repo, new_name)) => updated.push_str(&line.replacen(repo, new_name, 1)),
+                    None => updated.push_str(line),
+                }
+                updated.push('\n');
+            }
+            Ok(updated)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(repos: &[RepoEntry]) -> Vec<&str> {
+        repos.iter().map(RepoEntry::name).collect()
+    }
+
+    #[test]
+    fn parses_json5_with_comments() {
+        let data = "// Payments team\n[\n  \"acme/api\", /* legacy */ \"acme/web\",\n  { repo: \"acme/mono\", path: \"apps/web\", tags: [\"tier1\"] },\n]\n";
+        assert_eq!(Format::detect(data), Format::Json);
+        let repos = parse(Format::Json, data).unwrap();
+        assert_eq!(names(&repos), ["acme/api", "acme/web", "acme/mono"]);
+        assert_eq!(repos[2].file_path("package-lock.json"), "apps/web/package-lock.json");
+        assert_eq!(repos[2].tags(), ["tier1"]);
+    }
+
+    #[test]
+    fn parses_text_with_comments() {
+        let data = "# Payments team\nacme/api\n\nacme/web  # legacy\n";
+        assert_eq!(Format::detect(data), Format::Text);
+        assert_eq!(names(&parse(Format::Text, data).unwrap()), ["acme/api", "acme/web"]);
+    }
+
+    #[test]
+    fn parses_yaml_objects() {
+        let data = "- acme/api\n- repo: \"acme/web\"\n  ref: release\n";
+        assert_eq!(Format::of("repos.yml", data), Format::Yaml);
+        let repos = parse(Format::Yaml, data).unwrap();
+        assert_eq!(names(&repos), ["acme/api", "acme/web"]);
+        assert_eq!(repos[1].git_ref(), Some("release"));
+    }
+
+    #[test]
+    fn yaml_entries() {
+        assert_eq!(yaml_entry("- acme/api"), Some("acme/api"));
+        assert_eq!(yaml_entry("- repo: \"acme/web\""), Some("acme/web"));
+        assert_eq!(yaml_entry("  - repo: 'acme/web' # legacy"), Some("acme/web"));
+        assert_eq!(yaml_entry("  ref: release"), None);
+        assert_eq!(yaml_entry("# acme/old"), None);
+    }
+
+    #[test]
+    fn renames_json_keeping_comments() {
+        let data = "[\n  // legacy\n  \"acme/old\",\n  \"acme/web\",\n]\n";
+        let renames = BTreeMap::from([("acme/old", "acme/new")]);
+        assert_eq!(rename(Format::Json, data, &renames).unwrap(), "[\n  // legacy\n  \"acme/new\",\n  \"acme/web\",\n]\n");
+    }
+
+    #[test]
+    fn renames_plain_json_dropping_duplicates() {
+        let renames = BTreeMap::from([("acme/old", "acme/new")]);
+        let updated = rename(Format::Json, r#"["acme/new", "acme/old"]"#, &renames).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<String>>(&updated).unwrap(), ["acme/new"]);
+    }
+
+    #[test]
+    fn renames_text_dropping_duplicates() {
+        let data = "# team\nacme/old  # legacy\nacme/new\nacme/web\n";
+        let renames = BTreeMap::from([("acme/old", "acme/new")]);
+        assert_eq!(rename(Format::Text, data, &renames).unwrap(), "# team\nacme/new  # legacy\nacme/web\n");
+    }
+
+    #[test]
+    fn renames_yaml_in_place() {
+        let data = "- acme/old\n- repo: \"acme/old-web\"\n  ref: release\n";
+        let renames = BTreeMap::from([("acme/old", "acme/new"), ("acme/old-web", "acme/web")]);
+        assert_eq!(rename(Format::Yaml, data, &renames).unwrap(), "- acme/new\n- repo: \"acme/web\"\n  ref: release\n");
+    }
+}
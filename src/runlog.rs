@@ -0,0 +1,192 @@
+//! Run history (`ygg history`).
+//!
+//! Every audit or search appends its per-repository results to `history.sqlite3` in the cache directory, with
+//! the time it finished, whichever cache backend is in use. Unlike ".ygg-run.json" and the cache's results
+//! table, which only keep the latest run, nothing is overwritten, so the history answers questions like when a
+//! repository finally upgraded a package.
+
+use chrono::DateTime;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{unix_now, RepoState, Result, RunState, YggError};
+
+const DATABASE_FILE: &str = "history.sqlite3";
+
+/// How long to wait for another process's write transaction before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        finished_at INTEGER NOT NULL,
+        query TEXT NOT NULL,
+        filename TEXT NOT NULL,
+        interrupted INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS results (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        repo TEXT NOT NULL,
+        status TEXT NOT NULL,
+        value TEXT
+    );
+    CREATE INDEX IF NOT EXISTS results_repo ON results (repo, run_id);
+";
+
+/// A recorded run, with how many repositories it covered and matched.
+struct Run {
+    id: i64,
+    finished_at: i64,
+    query: String,
+    filename: String,
+    interrupted: bool,
+    repos: usize,
+    matched: usize,
+}
+
+/// Returns how a repository's outcome is recorded: its status, and the value found or why it failed.
+fn status(state: &RepoState) -> (&'static str, Option<&str>) {
+    match state {
+        RepoState::Completed { value } if value == "-------" => ("not_matched", None),
+        RepoState::Completed { value } => ("matched", Some(value)),
+        RepoState::NotFound => ("file_not_found", None),
+        RepoState::Blocked { reason } => ("blocked", Some(reason)),
+        RepoState::Failed { error } => ("failed", Some(error)),
+    }
+}
+
+fn format_time(unix: i64) -> String {
+    DateTime::from_timestamp(unix, 0).map_or_else(|| unix.to_string(), |time| time.format("%Y-%m-%d %H:%M").to_string())
+}
+
+fn open(cache_dir: &Path) -> Result<Connection> {
+    std::fs::create_dir_all(cache_dir)?;
+    let conn = Connection::open(cache_dir.join(DATABASE_FILE))?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+/// Appends a run's results to the history in `cache_dir`.
+pub(crate) fn record(cache_dir: &Path, state: &RunState, interrupted: bool) -> Result<()> {
+    let mut conn = open(cache_dir)?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO runs (finished_at, query, filename, interrupted) VALUES (?1, ?2, ?3, ?4)",
+        params![unix_now(), state.query, state.filename, interrupted],
+    )?;
+    let run_id = tx.last_insert_rowid();
+    {
+        let mut insert = tx.prepare("INSERT INTO results (run_id, repo, status, value) VALUES (?1, ?2, ?3, ?4)")?;
+        for (repo, repo_state) in &state.repos {
+            let (status, value) = status(repo_state);
+            insert.execute(params![run_id, repo, status, value])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Prints the most recent runs, newest first, optionally only those of `query`.
+pub(crate) fn print_runs(cache_dir: &Path, query: Option<&str>, limit: usize) -> Result<()> {
+    let conn = open(cache_dir)?;
+    let mut stmt = conn.prepare(
+        "SELECT runs.id, finished_at, query, filename, interrupted, COUNT(results.repo), COUNT(CASE WHEN status = 'matched' THEN 1 END)
+         FROM runs LEFT JOIN results ON results.run_id = runs.id
+         WHERE ?1 IS NULL OR query = ?1
+         GROUP BY runs.id ORDER BY runs.id DESC LIMIT ?2",
+    )?;
+    let runs = stmt
+        .query_map(params![query, limit], |row| {
+            Ok(Run {
+                id: row.get(0)?,
+                finished_at: row.get(1)?,
+                query: row.get(2)?,
+                filename: row.get(3)?,
+                interrupted: row.get(4)?,
+                repos: row.get(5)?,
+                matched: row.get(6)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if runs.is_empty() {
+        eprintln!("No runs recorded in {}", cache_dir.join(DATABASE_FILE).display());
+        return Ok(());
+    }
+    let width = runs.iter().map(|run| run.query.len()).max().unwrap_or(0);
+    for run in &runs {
+        println!(
+            "{:>5}  {}  {:<width$}  {:<20}  {} of {} matched{}",
+            run.id,
+            format_time(run.finished_at),
+            run.query,
+            run.filename,
+            run.matched,
+            run.repos,
+            if run.interrupted { " (interrupted)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+/// Prints every repository's result in run `id`.
+pub(crate) fn print_run(cache_dir: &Path, id: i64) -> Result<()> {
+    let conn = open(cache_dir)?;
+    let run: Option<(i64, String, String)> = conn
+        .query_row("SELECT finished_at, query, filename FROM runs WHERE id = ?1", [id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .optional()?;
+    let (finished_at, query, filename) = run.ok_or_else(|| YggError::Config(format!("no run {id} in the history")))?;
+    eprintln!("Run {id}: {query} in {filename}, finished {}\n", format_time(finished_at));
+
+    let mut stmt = conn.prepare("SELECT repo, status, value FROM results WHERE run_id = ?1 ORDER BY repo")?;
+    let rows = stmt
+        .query_map([id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, Option<String>>(2)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let width = rows.iter().map(|(repo, ..)| repo.len()).max().unwrap_or(0);
+    for (repo, status, value) in &rows {
+        println!("{repo:<width$}  {status:<14}  {}", value.as_deref().unwrap_or("-"));
+    }
+    Ok(())
+}
+
+/// Prints `repo`'s results over time, optionally only for `query`: only the runs where they changed, or every
+/// run with `all`.
+pub(crate) fn print_repo(cache_dir: &Path, repo: &str, query: Option<&str>, all: bool) -> Result<()> {
+    let conn = open(cache_dir)?;
+    let mut stmt = conn.prepare(
+        "SELECT runs.id, finished_at, query, status, value FROM results JOIN runs ON runs.id = results.run_id
+         WHERE repo = ?1 AND (?2 IS NULL OR query = ?2) ORDER BY runs.id",
+    )?;
+    let rows = stmt
+        .query_map(params![repo, query], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if rows.is_empty() {
+        eprintln!("No recorded runs covered {repo}");
+        return Ok(());
+    }
+
+    let width = rows.iter().map(|(_, _, query, ..)| query.len()).max().unwrap_or(0);
+    let mut last: std::collections::HashMap<&str, (&str, Option<&str>)> = std::collections::HashMap::new();
+    for (id, finished_at, query, status, value) in &rows {
+        let current = (status.as_str(), value.as_deref());
+        // Failed runs say nothing about the repository, so they don't count as changes
+        let changed = status != "failed" && last.get(query.as_str()) != Some(&current);
+        if changed {
+            last.insert(query, current);
+        }
+        if changed || all {
+            println!("{id:>5}  {}  {query:<width$}  {status:<14}  {}", format_time(*finished_at), value.as_deref().unwrap_or("-"));
+        }
+    }
+    Ok(())
+}
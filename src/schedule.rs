@@ -0,0 +1,210 @@
+//! Scheduled jobs for `ygg serve`.
+//!
+//! Each `[[jobs]]` table in .ygg.toml names a recurring audit: a cron schedule, the packages to audit (or a string
+//! to search a file for), the repositories, and where results go. One serve instance runs them all:
+//!
+//! ```toml
+//! [[jobs]]
+//! name = "nightly-lodash"
+//! schedule = "0 2 * * *"
+//! packages = ["lodash", "minimist"]
+//! repos = ["repos.json"]
+//! output = "reports/{job}-{package}.json"
+//! teams_webhook = "$TEAMS_WEBHOOK_URL"
+//! only_changes = true
+//! ```
+//!
+//! Schedules are in UTC, with five fields (minute, hour, day of month, month, day of week) or six with seconds
+//! first.
+
+use chrono::Utc;
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::serve::Server;
+use crate::{diff, discovery, findings, load_repo_lists, notify, unix_now, Result, RunState, YggError};
+
+/// A `[[jobs]]` table of .ygg.toml.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub(crate) struct Job {
+    name: String,
+    /// Cron expression, in UTC.
+    schedule: String,
+    /// Packages to audit in package-lock.json, one run each.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    packages: Vec<String>,
+    /// String to search `filename` for, instead of auditing packages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    search: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    /// Repos files to audit; defaults to the ones ygg serve was started with. Reread on every run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    repos: Vec<String>,
+    /// File to write each run's results to, with {job}, {package} and {timestamp} filled in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    /// URL to POST each run's results to, signed like --post-results.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    post_results: Option<String>,
+    /// Teams incoming webhook (or "$VAR") to post affected repositories to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    teams_webhook: Option<String>,
+    /// Only write and notify when results changed since the job's previous run.
+    #[serde(default)]
+    only_changes: bool,
+}
+
+impl Job {
+    /// Returns the queries this job runs, each with the file it reads and whether it is a package audit.
+    fn queries(&self) -> Result<Vec<(&str, &str, bool)>> {
+        match (&self.search, self.packages.is_empty()) {
+            (None, false) => {
+                let filename = self.filename.as_deref().unwrap_or("package-lock.json");
+                Ok(self.packages.iter().map(|package| (package.as_str(), filename, true)).collect())
+            }
+            (Some(search), true) => {
+                let filename =
+                    self.filename.as_deref().ok_or_else(|| YggError::Config(format!("job {}: search needs a filename", self.name)))?;
+                Ok(vec![(search.as_str(), filename, false)])
+            }
+            _ => Err(YggError::Config(format!("job {}: set either packages or search", self.name))),
+        }
+    }
+}
+
+/// Parses a cron expression, accepting the common five-field form by running at second zero.
+fn parse_schedule(expression: &str) -> std::result::Result<Schedule, cron::error::Error> {
+    let expression = expression.trim();
+    if expression.split_whitespace().count() == 5 {
+        Schedule::from_str(&format!("0 {expression}"))
+    } else {
+        Schedule::from_str(expression)
+    }
+}
+
+/// Checks every job's schedule and queries, so mistakes fail at startup instead of at 2am.
+pub(crate) fn validate(jobs: &[Job]) -> Result<()> {
+    let mut names = std::collections::HashSet::new();
+    for job in jobs {
+        if !names.insert(&job.name) {
+            return Err(YggError::Config(format!("two jobs are named {}", job.name)));
+        }
+        parse_schedule(&job.schedule).map_err(|e| YggError::Config(format!("job {}: invalid schedule {:?}: {e}", job.name, job.schedule)))?;
+        job.queries()?;
+    }
+    Ok(())
+}
+
+/// Runs every job on its schedule until the process exits. Each job keeps its own previous results, for
+/// `only_changes`.
+pub(crate) fn spawn(server: &Arc<Server>, jobs: Vec<Job>) {
+    for job in jobs {
+        let server = server.clone();
+        tokio::spawn(async move {
+            let schedule = parse_schedule(&job.schedule).expect("schedules are validated at startup");
+            let mut previous: HashMap<String, RunState> = HashMap::new();
+            // Looked up afresh after each run, so slots missed while a long run was going are skipped, not caught up
+            while let Some(next) = schedule.upcoming(Utc).next() {
+                let wait = (next - Utc::now()).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+                if let Err(e) = run_job(&server, &job, &mut previous).await {
+                    eprintln!("job {}: {e}", job.name);
+                }
+            }
+        });
+    }
+}
+
+/// Runs one job now: each query, then its output and notifications.
+async fn run_job(server: &Server, job: &Job, previous: &mut HashMap<String, RunState>) -> Result<()> {
+    let entries = if job.repos.is_empty() {
+        server.entries.clone()
+    } else {
+        load_repo_lists(&job.repos.iter().map(String::as_str).collect::<Vec<_>>())?
+    };
+    // Patterns such as "acme/payments-*" are expanded on every run, so new matching repositories are picked up
+    let entries = discovery::expand_patterns(&server.gh_client, &server.cache_manager, entries, &mut HashMap::new()).await?;
+    for (query, filename, is_package_lock) in job.queries()? {
+        let state = server.audit(&entries, filename, query, is_package_lock).await;
+        let found = state.found().count();
+        let changed = previous.get(query).map_or(true, |last| !diff::changes(last, &state).is_empty());
+        eprintln!("job {}: {query} found in {found} of {} repositories{}", job.name, entries.len(), if changed { "" } else { " (unchanged)" });
+        if changed || !job.only_changes {
+            deliver(server, job, &state, is_package_lock).await;
+        }
+        previous.insert(query.to_string(), state);
+    }
+    Ok(())
+}
+
+/// Writes and sends a run's results to each of the job's targets, reporting failures without stopping the rest.
+async fn deliver(server: &Server, job: &Job, state: &RunState, is_package_lock: bool) {
+    if let Some(template) = &job.output {
+        let path = template
+            .replace("{job}", &job.name)
+            .replace("{package}", &state.query.replace('/', "-"))
+            .replace("{timestamp}", &unix_now().to_string());
+        let written = Path::new(&path)
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&path, serde_json::to_vec_pretty(state).unwrap_or_default()));
+        if let Err(e) = written {
+            eprintln!("job {}: writing {path} failed: {e}", job.name);
+        }
+    }
+    if let Some(url) = &job.post_results {
+        let report = notify::results_report(state, false);
+        if let Err(e) = notify::post_results(&server.network, url, server.webhook_secret.as_deref(), &report).await {
+            eprintln!("job {}: posting results failed: {e}", job.name);
+        }
+    }
+    if let Some(webhook) = job.teams_webhook.as_deref().filter(|_| is_package_lock) {
+        let posted = async {
+            let findings = findings::from_state(&server.gh_client, state, None).await?;
+            if findings.findings.is_empty() {
+                return Ok(());
+            }
+            notify::send_teams(&server.network, &notify::resolve_secret(webhook, "teams_webhook")?, &findings).await
+        };
+        if let Err(e) = posted.await {
+            eprintln!("job {}: posting to Teams failed: {e}", job.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone};
+
+    fn next_after(expression: &str, after: DateTime<Utc>) -> DateTime<Utc> {
+        parse_schedule(expression).unwrap().after(&after).next().unwrap()
+    }
+
+    #[test]
+    fn five_fields_run_at_second_zero() {
+        let monday = Utc.with_ymd_and_hms(2024, 3, 4, 1, 30, 15).unwrap();
+        assert_eq!(next_after("0 2 * * *", monday), Utc.with_ymd_and_hms(2024, 3, 4, 2, 0, 0).unwrap());
+        assert_eq!(next_after("  30 6 * * Fri  ", monday), Utc.with_ymd_and_hms(2024, 3, 8, 6, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn six_and_seven_fields_are_passed_through() {
+        let monday = Utc.with_ymd_and_hms(2024, 3, 4, 1, 30, 15).unwrap();
+        assert_eq!(next_after("45 */15 * * * *", monday), Utc.with_ymd_and_hms(2024, 3, 4, 1, 30, 45).unwrap());
+        assert_eq!(next_after("0 0 12 1 1 * 2025", monday), Utc.with_ymd_and_hms(2025, 1, 1, 12, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(parse_schedule("").is_err());
+        assert!(parse_schedule("61 * * * *").is_err());
+        assert!(parse_schedule("every day").is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! HTTP API (`ygg serve`).
+//!
+//! Keeps one client and cache alive across requests, so other tools can query ygg without shelling out and
+//! repeated audits are answered from ETag revalidations instead of fresh downloads. Endpoints:
+//!
+//! - `GET /health`: `{"status": "ok"}`.
+//! - `POST /audit`: `{"package": "lodash", "repos": [...]}`, or `{"search": "text", "filename": "Dockerfile"}`,
+//!   returns the outcome of every repository in the shape of ".ygg-run.json". `repos` takes the same entries as a
+//!   repos file and defaults to the repos files given at startup.
+//! - `GET /results`: the latest results of every query audited so far, by request or by a scheduled job.
+//! - `POST /webhook`: GitHub webhook deliveries. A push to a repository's default branch that touches a file
+//!   in the latest results refetches just that file and updates the results, so they stay current between full
+//!   runs. Deliveries must be signed with YGG_GITHUB_WEBHOOK_SECRET ("github_webhook_secret" in .ygg.toml).
+//!
+//! When YGG_SERVE_TOKEN is set, requests other than webhook deliveries must carry it as a bearer token. Jobs in
+//! .ygg.toml run on their schedules alongside the API (see `schedule`).
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::stream::{self, StreamExt};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::cache::CacheManager;
+use crate::repolist::RepoEntry;
+use crate::schedule::{self, Job};
+use crate::{contents_url, notify, process_file, GitHubClient, NetworkOptions, RepoOutcome, Result, RunState, YggError};
+
+/// Repositories one request may audit, so a single call can't exhaust the rate limit.
+const MAX_AUDIT_REPOS: usize = 5000;
+
+/// What `ygg serve` shares between requests.
+pub(crate) struct Server {
+    pub(crate) gh_client: GitHubClient,
+    pub(crate) cache_manager: CacheManager,
+    /// Repositories audited when a request names none, with patterns expanded at startup.
+    pub(crate) entries: Vec<RepoEntry>,
+    pub(crate) parallel: usize,
+    /// For scheduled jobs' notifications.
+    pub(crate) network: NetworkOptions,
+    pub(crate) webhook_secret: Option<String>,
+    /// Secret GitHub signs webhook deliveries with; the receiver is off without one.
+    github_webhook_secret: Option<String>,
+    /// Bearer token requests must carry, from YGG_SERVE_TOKEN.
+    token: Option<String>,
+    /// Latest results of each query, and whether it was a package audit, updated by audits and pushes.
+    results: Mutex<BTreeMap<String, (RunState, bool)>>,
+}
+
+#[derive(Deserialize)]
+struct AuditRequest {
+    #[serde(default)]
+    package: Option<String>,
+    #[serde(default)]
+    search: Option<String>,
+    #[serde(default)]
+    filename: Option<String>,
+    #[serde(default)]
+    repos: Option<Vec<RepoEntry>>,
+}
+
+/// An error response: the status and `{"error": message}`.
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl Server {
+    pub(crate) fn new(
+        gh_client: GitHubClient,
+        cache_manager: CacheManager,
+        entries: Vec<RepoEntry>,
+        parallel: usize,
+        network: NetworkOptions,
+        webhook_secret: Option<String>,
+        github_webhook_secret: Option<String>,
+    ) -> Self {
+        let token = env::var("YGG_SERVE_TOKEN").ok().filter(|token| !token.is_empty());
+        let results = Mutex::default();
+        Self { gh_client, cache_manager, entries, parallel, network, webhook_secret, github_webhook_secret, token, results }
+    }
+
+    fn authorize(&self, headers: &HeaderMap) -> std::result::Result<(), ApiError> {
+        let Some(token) = &self.token else {
+            return Ok(());
+        };
+        let given = headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()).and_then(|value| value.strip_prefix("Bearer "));
+        if given.is_some_and(|given| tokens_match(given, token)) {
+            Ok(())
+        } else {
+            Err(ApiError(StatusCode::UNAUTHORIZED, "missing or wrong bearer token".to_string()))
+        }
+    }
+
+    /// Reads `filename` in every entry and extracts the package version, or whether `query` occurs, at most
+    /// `parallel` repositories at a time. The results replace the latest ones for `query`.
+    pub(crate) async fn audit(&self, entries: &[RepoEntry], filename: &str, query: &str, is_package_lock: bool) -> RunState {
+        let state = self.fetch(entries, filename, query, is_package_lock).await;
+        self.results.lock().unwrap().insert(query.to_string(), (state.clone(), is_package_lock));
+        state
+    }
+
+    async fn fetch(&self, entries: &[RepoEntry], filename: &str, query: &str, is_package_lock: bool) -> RunState {
+        // Owned per task, so the future stays Send for the handler
+        let uris: Vec<String> = entries.iter().map(|entry| contents_url(entry.name(), &entry.file_path(filename), entry.git_ref())).collect();
+        let results: Vec<Result<RepoOutcome>> = stream::iter(uris)
+            .map(|uri| {
+                let gh_client = self.gh_client.clone();
+                let cache_manager = self.cache_manager.clone();
+                let query = query.to_string();
+                async move {
+                    let fetched = gh_client.fetch_raw_file(&uri, &cache_manager).await?;
+                    let value = tokio::task::spawn_blocking(move || process_file(&fetched.body, is_package_lock, &query))
+                        .await
+                        .unwrap_or_else(|_| "-------".to_string());
+                    Ok(RepoOutcome { value, renamed_to: None, nested: Vec::new(), blame: None })
+                }
+            })
+            .buffered(self.parallel)
+            .collect()
+            .await;
+        self.cache_manager.flush();
+        let repos: Vec<String> = entries.iter().map(|entry| entry.name().to_string()).collect();
+        RunState::from_results(query, filename, &repos, &results)
+    }
+
+    /// Returns the entry for `repo`: its entry in the repos files, with its directory and ref, or the bare name.
+    fn entry(&self, repo: &str) -> RepoEntry {
+        self.entries.iter().find(|entry| entry.name() == repo).cloned().unwrap_or_else(|| RepoEntry::Name(repo.to_string()))
+    }
+
+    /// Refetches `repo`'s file in every latest result that read one of `paths`, bypassing the cache, and updates
+    /// the results.
+    async fn refresh(&self, repo: &str, paths: &[&str]) {
+        let entry = self.entry(repo);
+        let stale: Vec<(String, String, bool)> = self
+            .results
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|(state, _)| state.repos.contains_key(repo) && paths.contains(&entry.file_path(&state.filename).as_str()))
+            .map(|(state, is_package_lock)| (state.query.clone(), state.filename.clone(), *is_package_lock))
+            .collect();
+        for (query, filename, is_package_lock) in stale {
+            self.cache_manager.invalidate(&contents_url(repo, &entry.file_path(&filename), entry.git_ref()));
+            let fresh = self.fetch(std::slice::from_ref(&entry), &filename, &query, is_package_lock).await;
+            if let Some((state, _)) = self.results.lock().unwrap().get_mut(&query) {
+                state.repos.extend(fresh.repos);
+            }
+            eprintln!("Refreshed {repo} for {query} after a push");
+        }
+    }
+
+    /// Revalidates the cached package-lock.json of every default repository, so requests find a warm cache.
+    async fn warm(&self) {
+        let started = std::time::Instant::now();
+        stream::iter(&self.entries)
+            .for_each_concurrent(self.parallel, |entry| async move {
+                let uri = contents_url(entry.name(), &entry.file_path("package-lock.json"), entry.git_ref());
+                let _ = self.gh_client.fetch_raw_file(&uri, &self.cache_manager).await;
+            })
+            .await;
+        self.cache_manager.flush();
+        eprintln!("Warmed the cache for {} repositories in {:.1?}", self.entries.len(), started.elapsed());
+    }
+}
+
+/// Compares a given token with the expected one in constant time, by checking a MAC of one against the other, so
+/// response times don't reveal how much of a guess was right.
+fn tokens_match(given: &str, expected: &str) -> bool {
+    let mac = |value: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(expected.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(value.as_bytes());
+        mac
+    };
+    mac(expected).verify_slice(&mac(given).finalize().into_bytes()).is_ok()
+}
+
+async fn health() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn results(State(server): State<Arc<Server>>, headers: HeaderMap) -> std::result::Result<Json<Vec<RunState>>, ApiError> {
+    server.authorize(&headers)?;
+    Ok(Json(server.results.lock().unwrap().values().map(|(state, _)| state.clone()).collect()))
+}
+
+#[derive(Deserialize)]
+struct PushEvent {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    repository: PushRepository,
+    #[serde(default)]
+    commits: Vec<PushCommit>,
+}
+
+#[derive(Deserialize)]
+struct PushRepository {
+    full_name: String,
+    default_branch: String,
+}
+
+#[derive(Deserialize)]
+struct PushCommit {
+    #[serde(default)]
+    added: Vec<String>,
+    #[serde(default)]
+    modified: Vec<String>,
+    #[serde(default)]
+    removed: Vec<String>,
+}
+
+async fn webhook(State(server): State<Arc<Server>>, headers: HeaderMap, body: Bytes) -> std::result::Result<StatusCode, ApiError> {
+    let Some(secret) = &server.github_webhook_secret else {
+        return Err(ApiError(StatusCode::NOT_FOUND, "the webhook receiver needs YGG_GITHUB_WEBHOOK_SECRET".to_string()));
+    };
+    let signature = headers.get("X-Hub-Signature-256").and_then(|value| value.to_str().ok()).unwrap_or_default();
+    if !notify::verify_signature(secret, &body, signature) {
+        return Err(ApiError(StatusCode::UNAUTHORIZED, "invalid X-Hub-Signature-256".to_string()));
+    }
+    let event = headers.get("X-GitHub-Event").and_then(|value| value.to_str().ok()).unwrap_or_default();
+    if event != "push" {
+        // Including the ping GitHub sends when the webhook is created
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    let push: PushEvent =
+        serde_json::from_slice(&body).map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("invalid push payload: {e}")))?;
+    if push.git_ref != format!("refs/heads/{}", push.repository.default_branch) {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+
+    // Answer right away; GitHub gives up on deliveries after ten seconds
+    tokio::spawn(async move {
+        let paths: Vec<&str> = push
+            .commits
+            .iter()
+            .flat_map(|commit| commit.added.iter().chain(&commit.modified).chain(&commit.removed))
+            .map(String::as_str)
+            .collect();
+        server.refresh(&push.repository.full_name, &paths).await;
+    });
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn audit(
+    State(server): State<Arc<Server>>,
+    headers: HeaderMap,
+    Json(request): Json<AuditRequest>,
+) -> std::result::Result<Json<RunState>, ApiError> {
+    server.authorize(&headers)?;
+    let (query, is_package_lock) = match (request.package, request.search) {
+        (Some(package), None) => (package, true),
+        (None, Some(search)) => (search, false),
+        _ => return Err(ApiError(StatusCode::BAD_REQUEST, "give exactly one of \"package\" and \"search\"".to_string())),
+    };
+    let filename = match (request.filename, is_package_lock) {
+        (Some(filename), _) => filename,
+        (None, true) => "package-lock.json".to_string(),
+        (None, false) => return Err(ApiError(StatusCode::BAD_REQUEST, "\"search\" needs a \"filename\"".to_string())),
+    };
+    let entries = request.repos.unwrap_or_else(|| server.entries.clone());
+    if entries.is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "no repositories: pass \"repos\" or start ygg serve with --repos".to_string()));
+    }
+    if entries.len() > MAX_AUDIT_REPOS {
+        return Err(ApiError(StatusCode::PAYLOAD_TOO_LARGE, format!("at most {MAX_AUDIT_REPOS} repositories per request")));
+    }
+    if entries.iter().any(RepoEntry::is_pattern) {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "repository patterns are not supported; name each repository".to_string()));
+    }
+    Ok(Json(server.audit(&entries, &filename, &query, is_package_lock).await))
+}
+
+/// Serves the API on `listen` and runs `jobs` on their schedules until Ctrl-C, revalidating the default
+/// repositories' lockfiles every `warm` if given.
+pub(crate) async fn run(server: Server, listen: SocketAddr, warm: Option<Duration>, jobs: Vec<Job>) -> Result<()> {
+    schedule::validate(&jobs)?;
+    let server = Arc::new(server);
+    let job_count = jobs.len();
+    schedule::spawn(&server, jobs);
+    if let Some(interval) = warm.filter(|_| !server.entries.is_empty()) {
+        let server = server.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                server.warm().await;
+            }
+        });
+    }
+
+    let app = Router::new()
+        .route("/health", get(health))
+        .route("/audit", post(audit))
+        .route("/results", get(results))
+        .route("/webhook", post(webhook))
+        .with_state(server.clone());
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| YggError::Config(format!("can't listen on {listen}: {e}")))?;
+    eprintln!(
+        "Serving on http://{listen} with {} default repositories and {job_count} scheduled jobs{}",
+        server.entries.len(),
+        if server.token.is_some() { "; requests need YGG_SERVE_TOKEN" } else { "" }
+    );
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    server.cache_manager.finish().await;
+    Ok(())
+}
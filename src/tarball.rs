@@ -0,0 +1,61 @@
+//! Whole-repository analysis from a downloaded tarball.
+//!
+//! Downloading `/tarball` once gives every file in the repository, so nested copies of the target file (e.g. one
+//! lockfile per workspace) can be found without a request per file, and without the contents API's 1MB limit.
+
+use flate2::read::GzDecoder;
+use reqwest::Method;
+use std::io::Read;
+
+use crate::{api_url, GitHubClient, Result, YggError};
+
+/// Downloads the repository tarball at `git_ref`, or its default branch.
+pub(crate) async fn download(gh_client: &GitHubClient, repo: &str, git_ref: Option<&str>) -> Result<Vec<u8>> {
+    let url = match git_ref {
+        Some(git_ref) => format!("{}/repos/{repo}/tarball/{git_ref}", api_url()),
+        None => format!("{}/repos/{repo}/tarball", api_url()),
+    };
+    // The API redirects to codeload.github.com with a short-lived token in the URL
+    let resp = gh_client.send(gh_client.api_request(Method::GET, &url)).await?;
+
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(YggError::NotFound);
+    }
+    if !resp.status().is_success() {
+        return Err(YggError::UnexpectedStatus(resp.status()));
+    }
+
+    Ok(resp.bytes().await?.to_vec())
+}
+
+/// Extracts every file whose path is `filename` or ends with `/filename`, skipping anything under node_modules.
+///
+/// Paths are returned relative to the repository root, sorted so the root-level file comes first.
+pub(crate) fn extract_matching(tarball: &[u8], filename: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    let suffix = format!("/{filename}");
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+    let mut files = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        // Entries are prefixed with a "{owner}-{repo}-{sha}/" directory
+        let full_path = entry.path()?.to_string_lossy().into_owned();
+        let Some((_, path)) = full_path.split_once('/') else {
+            continue;
+        };
+        if path.contains("node_modules/") || (path != filename && !path.ends_with(&suffix)) {
+            continue;
+        }
+
+        let mut body = Vec::new();
+        entry.read_to_end(&mut body)?;
+        files.push((path.to_string(), body));
+    }
+
+    files.sort_by_key(|(path, _)| (path != filename, path.clone()));
+    Ok(files)
+}